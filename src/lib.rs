@@ -1,7 +1,7 @@
 pub mod tree;
 mod quartet;
 mod resolution;
-mod rooting;
+pub mod rooting;
 mod export;
 mod utils;
 
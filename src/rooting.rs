@@ -0,0 +1,240 @@
+// This module roots gene trees without an outgroup, so rooted-triplet and rooted-quartet analyses can run
+// on trees that arrive unrooted from ML software. Two criteria are provided:
+// - MAD (minimal ancestor deviation, Tria et al. 2017): the root position that makes every pair of leaves
+//   as close as possible to equidistant from their last common ancestor;
+// - minimum variance (MinVar): the root position minimising the variance of root-to-tip distances.
+// Each candidate edge is scored with the best position along it, and the best edge wins. The ambiguity
+// index is the best score divided by the second best: values close to 1 mean the root is uncertain.
+use crate::tree::operations::TreeError;
+use crate::tree::parser::{NewickReader, ParseError};
+use crate::tree::structure::{Tree, NodeId};
+use crate::tree::writer::NewickOptions;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+// A tree rooted by MAD or MinVar, with the score of the chosen root.
+#[derive(Debug, Clone)]
+pub struct RootedTree {
+    pub tree: Tree, // The rooted copy of the input tree
+    pub score: f64, // MAD: root-mean-square relative ancestor deviation; MinVar: root-to-tip variance
+    pub ambiguity: f64, // Best score / second best score over all edges (1.0 = fully ambiguous)
+}
+
+// Errors from batch rooting of a tree file.
+#[derive(Debug)]
+pub enum RootingError {
+    Parse(ParseError), // A tree in the input could not be read
+    Tree(TreeError), // A tree could not be rooted (too few leaves, no branch lengths)
+    Io(io::Error), // Writing the output failed
+}
+
+impl fmt::Display for RootingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RootingError::Parse(e) => write!(f, "{}", e),
+            RootingError::Tree(e) => write!(f, "{}", e),
+            RootingError::Io(e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RootingError {}
+
+impl From<ParseError> for RootingError {
+    fn from(err: ParseError) -> Self {
+        RootingError::Parse(err)
+    }
+}
+
+impl From<TreeError> for RootingError {
+    fn from(err: TreeError) -> Self {
+        RootingError::Tree(err)
+    }
+}
+
+impl From<io::Error> for RootingError {
+    fn from(err: io::Error) -> Self {
+        RootingError::Io(err)
+    }
+}
+
+// Leaf-to-node patristic distances shared by both criteria, plus the leaves below every edge.
+struct EdgeDistances {
+    leaves: Vec<NodeId>, // Leaves of the tree
+    dist: Vec<Vec<f64>>, // dist[i][node] = patristic distance from leaves[i] to node
+    below: Vec<Vec<bool>>, // below[node][i] = leaves[i] is in the clade of node
+}
+
+impl EdgeDistances {
+    fn new(tree: &Tree) -> Result<Self, TreeError> {
+        let leaves = tree.leaves();
+        if leaves.len() < 3 {
+            return Err(TreeError::TooFewLeaves(leaves.len()));
+        }
+        if !tree.nodes.iter().any(|n| n.length_to_parent.is_some_and(|l| l > 0.0)) {
+            return Err(TreeError::NoBranchLengths);
+        }
+
+        let dist = leaves.iter()
+            .map(|&leaf| (0..tree.nodes.len()).map(|node| tree.patristic_distance(leaf, node).unwrap_or(0.0)).collect())
+            .collect();
+
+        let mut below = vec![vec![false; leaves.len()]; tree.nodes.len()];
+        for (i, &leaf) in leaves.iter().enumerate() {
+            for node in tree.ancestors(leaf) {
+                below[node][i] = true;
+            }
+        }
+        Ok(EdgeDistances { leaves, dist, below })
+    }
+}
+
+// Copy of the tree with a bifurcating root suppressed, so the two root edges are scored as one edge.
+fn unrooted_copy(tree: &Tree) -> Result<Tree, TreeError> {
+    let mut base = tree.clone();
+    let children = tree.children(tree.root);
+    if children.len() == 2
+        && let Some(&internal) = children.iter().find(|&&c| !tree.is_leaf(c))
+    {
+        base.reroot_at_node(internal)?;
+    }
+    Ok(base)
+}
+
+// Picks the best and second best scores and returns the rooted copy of the tree.
+fn root_at_best(tree: &Tree, scored: Vec<(NodeId, f64, f64)>) -> Result<RootedTree, TreeError> {
+    let mut best: Option<(NodeId, f64, f64)> = None; // (edge child, fraction from child, score)
+    let mut second = f64::INFINITY;
+    for (child, fraction, score) in scored {
+        match best {
+            Some((_, _, s)) if score >= s => second = second.min(score),
+            _ => {
+                if let Some((_, _, s)) = best {
+                    second = second.min(s);
+                }
+                best = Some((child, fraction, score));
+            }
+        }
+    }
+
+    let (child, fraction, score) = best.ok_or(TreeError::TooFewLeaves(0))?;
+    let mut rooted = tree.clone();
+    rooted.reroot_on_edge(child, fraction)?;
+    let ambiguity = if second.is_finite() && second > 0.0 { score / second } else { 1.0 };
+    Ok(RootedTree { tree: rooted, score, ambiguity })
+}
+
+// Rooting by MAD and minimum variance
+impl Tree {
+    // Roots a copy of the tree by minimal ancestor deviation. For a root on the edge above node c (length L,
+    // parent p), a pair of leaves on the same side has its ancestor at the median of the pair and the other
+    // end of the edge; a pair across the edge has the root itself as ancestor, at distance d(x, c) + ρ from
+    // the leaf x below it. The relative deviation of a pair is |2·d(x, ancestor)/d(x, y) − 1|, and ρ is the
+    // closed-form minimiser of the squared deviations of the crossing pairs, clipped to [0, L].
+    pub fn mad_root(&self) -> Result<RootedTree, TreeError> {
+        let base = unrooted_copy(self)?;
+        let ed = EdgeDistances::new(&base)?;
+        let n = ed.leaves.len();
+        let d = |i: usize, j: usize| ed.dist[i][ed.leaves[j]]; // Leaf-to-leaf distance
+        let mut scored = Vec::new();
+
+        for c in 0..base.nodes.len() {
+            let Some(p) = base.nodes[c].parent else { continue };
+            let length = base.nodes[c].length_to_parent.unwrap_or(0.0);
+            let inside = &ed.below[c];
+
+            // Optimal position of the root along the edge, from the pairs crossing it.
+            let (mut num, mut den) = (0.0, 0.0);
+            for x in (0..n).filter(|&x| inside[x]) {
+                for y in (0..n).filter(|&y| !inside[y]) {
+                    let dxy = d(x, y);
+                    if dxy > 0.0 {
+                        num += (dxy - 2.0 * ed.dist[x][c]) / (dxy * dxy);
+                        den += 1.0 / (dxy * dxy);
+                    }
+                }
+            }
+            let rho = if den > 0.0 { (num / (2.0 * den)).clamp(0.0, length) } else { length / 2.0 };
+
+            // Mean squared relative deviation over all pairs for that root position.
+            let (mut sum, mut pairs) = (0.0, 0usize);
+            for x in 0..n {
+                for y in x + 1..n {
+                    let dxy = d(x, y);
+                    if dxy <= 0.0 {
+                        continue;
+                    }
+                    let to_ancestor = match (inside[x], inside[y]) {
+                        (true, true) => (dxy + ed.dist[x][c] - ed.dist[y][c]) / 2.0,
+                        (false, false) => (dxy + ed.dist[x][p] - ed.dist[y][p]) / 2.0,
+                        (true, false) => ed.dist[x][c] + rho,
+                        (false, true) => ed.dist[y][c] + rho, // Measured from y, the leaf below the edge
+                    };
+                    let deviation = 2.0 * to_ancestor / dxy - 1.0;
+                    sum += deviation * deviation;
+                    pairs += 1;
+                }
+            }
+            let score = if pairs > 0 { (sum / pairs as f64).sqrt() } else { 0.0 };
+            let fraction = if length > 0.0 { rho / length } else { 0.0 };
+            scored.push((c, fraction, score));
+        }
+
+        root_at_best(&base, scored)
+    }
+
+    // Roots a copy of the tree at the point minimising the variance of root-to-tip distances. On the edge
+    // above c, tips below move away from the root by ρ and the others move closer, so the variance is a
+    // quadratic in ρ with minimiser −Cov(a, s)/Var(s), clipped to the edge.
+    pub fn min_var_root(&self) -> Result<RootedTree, TreeError> {
+        let base = unrooted_copy(self)?;
+        let ed = EdgeDistances::new(&base)?;
+        let n = ed.leaves.len() as f64;
+        let mut scored = Vec::new();
+
+        for c in 0..base.nodes.len() {
+            let Some(p) = base.nodes[c].parent else { continue };
+            let length = base.nodes[c].length_to_parent.unwrap_or(0.0);
+
+            // Root-to-tip distance of leaf i is a_i + s_i·ρ with ρ measured from c.
+            let terms: Vec<(f64, f64)> = (0..ed.leaves.len())
+                .map(|i| if ed.below[c][i] { (ed.dist[i][c], 1.0) } else { (ed.dist[i][p] + length, -1.0) })
+                .collect();
+            let mean_a = terms.iter().map(|t| t.0).sum::<f64>() / n;
+            let mean_s = terms.iter().map(|t| t.1).sum::<f64>() / n;
+            let cov = terms.iter().map(|t| (t.0 - mean_a) * (t.1 - mean_s)).sum::<f64>() / n;
+            let var_s = terms.iter().map(|t| (t.1 - mean_s).powi(2)).sum::<f64>() / n;
+
+            let rho = if var_s > 0.0 { (-cov / var_s).clamp(0.0, length) } else { length / 2.0 };
+            let values: Vec<f64> = terms.iter().map(|t| t.0 + t.1 * rho).collect();
+            let mean = values.iter().sum::<f64>() / n;
+            let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n;
+
+            let fraction = if length > 0.0 { rho / length } else { 0.0 };
+            scored.push((c, fraction, variance));
+        }
+
+        root_at_best(&base, scored)
+    }
+}
+
+// MAD-roots every tree of a stream, e.g. a NewickReader over a gene tree file. A tree that cannot be read
+// or rooted yields an Err and the following trees are still processed.
+pub fn mad_root_all<I>(trees: I) -> impl Iterator<Item = Result<RootedTree, RootingError>>
+where
+    I: IntoIterator<Item = Result<Tree, ParseError>>,
+{
+    trees.into_iter().map(|tree| Ok(tree?.mad_root()?))
+}
+
+// Reads a Newick gene tree file, MAD-roots each tree and writes the rooted trees one per line.
+// Returns the ambiguity index of every tree, in file order. Stops at the first tree that fails.
+pub fn mad_root_newick<R: BufRead, W: Write>(input: R, output: &mut W) -> Result<Vec<f64>, RootingError> {
+    let mut ambiguities = Vec::new();
+    for rooted in mad_root_all(NewickReader::new(input)) {
+        let rooted = rooted?;
+        rooted.tree.write_newick(output, &NewickOptions::default())?;
+        ambiguities.push(rooted.ambiguity);
+    }
+    Ok(ambiguities)
+}
@@ -0,0 +1,168 @@
+// This module puts trees in a canonical form and hashes their topology, so gene trees that differ only
+// in child order (or branch lengths, supports and internal labels) can be recognised as the same tree.
+// Children are ordered by leaf count, then by the smallest taxon id below them; bind trees to a shared
+// TaxonSet first, as leaves without a taxon id are keyed by a hash of their label instead (in both the
+// rooted and the unrooted forms).
+// Hashes use 64-bit FNV-1a, so they are stable across runs, platforms and crate versions.
+use super::structure::{Tree, NodeId};
+use super::splits::{Bitset, Split};
+use std::collections::HashMap;
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+const OPEN: u64 = u64::MAX; // Token for the start of an internal node
+const CLOSE: u64 = u64::MAX - 1; // Token for the end of an internal node
+const LABEL_KEY: u64 = 1 << 62; // Set on keys of leaves without a taxon id, above any real taxon id
+
+fn fnv(hash: u64, value: u64) -> u64 { // Feeds the 8 bytes of value into an FNV-1a hash
+    value.to_le_bytes().iter().fold(hash, |h, &b| (h ^ b as u64).wrapping_mul(FNV_PRIME))
+}
+
+fn fnv_str(s: &str) -> u64 {
+    s.bytes().fold(FNV_OFFSET, |h, b| (h ^ b as u64).wrapping_mul(FNV_PRIME))
+}
+
+// Trees sharing one topology within a collection.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopologyClass {
+    pub hash: u64, // topology_hash (rooted) or unrooted_topology_hash of the members
+    pub trees: Vec<usize>, // Indices of the trees with this topology, in input order
+}
+
+impl TopologyClass {
+    pub fn count(&self) -> usize { // Multiplicity of the topology
+        self.trees.len()
+    }
+
+    pub fn representative(&self) -> usize { // Index of the first tree with this topology
+        self.trees[0]
+    }
+}
+
+// Canonical order and topology hashes
+impl Tree {
+    // Reorders the children of every node into canonical order: fewer leaves first, then smaller taxon id.
+    pub fn canonicalize(&mut self) {
+        for (id, children) in self.canonical_children().into_iter().enumerate() {
+            self.nodes[id].children = children;
+        }
+    }
+
+    // Hash of the rooted topology and leaf set, independent of child order, lengths, supports and
+    // internal labels. Equal for trees that canonicalize to the same shape.
+    pub fn topology_hash(&self) -> u64 {
+        self.topology_tokens().into_iter().fold(FNV_OFFSET, fnv)
+    }
+
+    // Hash of the unrooted topology: the leaf set and the non-trivial splits, so the position of the root
+    // does not matter.
+    pub fn unrooted_topology_hash(&self) -> u64 {
+        self.unrooted_tokens().into_iter().fold(FNV_OFFSET, fnv)
+    }
+
+    // Sort key of a leaf: its taxon id, or a hash of its label when it has none.
+    fn leaf_key(&self, node: NodeId) -> u64 {
+        match self.nodes[node].taxon {
+            Some(t) => t as u64,
+            None => LABEL_KEY | (fnv_str(self.node_label(node).unwrap_or_default()) >> 2),
+        }
+    }
+
+    // Children of every node (index = NodeId) in canonical order.
+    fn canonical_children(&self) -> Vec<Vec<NodeId>> {
+        let mut keys = vec![(0usize, u64::MAX); self.nodes.len()]; // (leaf count, smallest leaf key) per node
+        let mut ordered = vec![Vec::new(); self.nodes.len()];
+        for node in self.postorder_iter() {
+            let mut children = self.nodes[node].children.clone();
+            if children.is_empty() {
+                keys[node] = (1, self.leaf_key(node));
+                continue;
+            }
+            children.sort_by_key(|&c| keys[c]);
+            keys[node] = children.iter().fold((0, u64::MAX), |(n, k), &c| (n + keys[c].0, k.min(keys[c].1)));
+            ordered[node] = children;
+        }
+        ordered
+    }
+
+    // The rooted topology as a token sequence: OPEN ... CLOSE around internal nodes, leaf keys for leaves.
+    fn topology_tokens(&self) -> Vec<u64> {
+        let children = self.canonical_children();
+        let mut tokens = Vec::with_capacity(2 * self.nodes.len());
+        let mut stack = if self.nodes.is_empty() { Vec::new() } else { vec![Some(self.root)] }; // None = close the node
+        while let Some(step) = stack.pop() {
+            match step {
+                Some(node) if children[node].is_empty() => tokens.push(self.leaf_key(node)),
+                Some(node) => {
+                    tokens.push(OPEN);
+                    stack.push(None);
+                    stack.extend(children[node].iter().rev().map(|&c| Some(c)));
+                }
+                None => tokens.push(CLOSE),
+            }
+        }
+        tokens
+    }
+
+    // The unrooted topology as a token sequence: the sorted leaf keys, then each non-trivial split as the
+    // positions (in that order) of the leaves on its canonical side. Splits are built over leaf keys rather
+    // than taxon ids, so trees without a TaxonSet are split by label instead of having no splits at all.
+    fn unrooted_tokens(&self) -> Vec<u64> {
+        let mut leaves: Vec<(u64, NodeId)> = self.leaves().into_iter().map(|l| (self.leaf_key(l), l)).collect();
+        leaves.sort_unstable();
+        let n = leaves.len();
+        let mut position = vec![0; self.nodes.len()];
+        for (i, &(_, leaf)) in leaves.iter().enumerate() {
+            position[leaf] = i;
+        }
+
+        let mut clades = vec![Bitset::new(n); self.nodes.len()]; // Leaf positions below each node
+        for node in self.postorder_iter() {
+            if self.nodes[node].children.is_empty() {
+                clades[node].insert(position[node]);
+            }
+            for &child in &self.nodes[node].children {
+                let below = clades[child].clone();
+                clades[node].union_with(&below);
+            }
+        }
+
+        let all = Bitset::from_ids(n, 0..n);
+        let mut splits: Vec<Bitset> = self.preorder_iter()
+            .filter(|&node| node != self.root)
+            .filter(|&node| (2..=n.saturating_sub(2)).contains(&clades[node].count())) // Non-trivial splits only
+            .map(|node| Split::canonical(clades[node].clone(), &all, None, None).bits)
+            .collect();
+        splits.sort();
+        splits.dedup(); // Both edges below a bifurcating root induce the same split
+
+        let mut tokens: Vec<u64> = leaves.iter().map(|&(key, _)| key).collect();
+        for split in splits {
+            tokens.push(OPEN);
+            tokens.extend(split.iter().map(|i| i as u64));
+        }
+        tokens
+    }
+}
+
+// Groups trees by topology and returns the classes by decreasing multiplicity (ties in order of first
+// appearance). With `rooted` false, trees that differ only in their root fall into the same class. Bind
+// the trees to one TaxonSet or leave them all unbound, as taxon ids and labels are keyed differently.
+// Grouping compares full token sequences, so hash collisions cannot merge different topologies.
+pub fn unique_topologies(trees: &[Tree], rooted: bool) -> Vec<TopologyClass> {
+    let mut classes: Vec<TopologyClass> = Vec::new();
+    let mut by_tokens: HashMap<Vec<u64>, usize> = HashMap::new(); // Token sequence -> index in classes
+    for (i, tree) in trees.iter().enumerate() {
+        let tokens = if rooted { tree.topology_tokens() } else { tree.unrooted_tokens() };
+        match by_tokens.get(&tokens) {
+            Some(&class) => classes[class].trees.push(i),
+            None => {
+                let hash = tokens.iter().copied().fold(FNV_OFFSET, fnv);
+                by_tokens.insert(tokens, classes.len());
+                classes.push(TopologyClass { hash, trees: vec![i] });
+            }
+        }
+    }
+    classes.sort_by_key(|class| std::cmp::Reverse(class.count())); // Stable, so ties keep first appearance
+    classes
+}
@@ -0,0 +1,328 @@
+// This module compares whole trees with each other, as opposed to the node-to-node distances in
+// operations.rs. Trees must be bound to the same TaxonSet (Tree::bind_taxa); when their taxa differ, the
+// comparison is made on the subtrees induced by the taxa they share. Comparing a tree with a leaf that
+// has no taxon id is an error (TreeError::UnboundLeaf).
+use super::structure::Tree;
+use super::operations::TreeError;
+use super::splits::{Bitset, Split};
+use std::collections::HashMap;
+
+// Result of a Robinson–Foulds comparison.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RfDistance {
+    pub distance: usize, // Number of splits found in only one of the two trees
+    pub max: usize, // Largest possible distance for two binary trees on the shared taxa, 2(n - 3)
+    pub normalized: f64, // distance / max, in [0, 1] (0 when there are fewer than four shared taxa)
+    pub shared_taxa: usize, // Number of taxa the comparison was made on
+}
+
+impl Tree {
+    // Taxa present in both trees, after checking that both are bound.
+    fn shared_taxa(&self, other: &Tree, n_taxa: usize) -> Result<Bitset, TreeError> {
+        self.check_bound()?;
+        other.check_bound()?;
+        let mut shared = self.taxon_bitset(n_taxa);
+        shared.intersect_with(&other.taxon_bitset(n_taxa));
+        Ok(shared)
+    }
+
+    // Robinson–Foulds distance: the number of non-trivial splits present in one tree but not the other,
+    // computed on the taxa the trees share. Rooting and child order do not matter.
+    pub fn robinson_foulds(&self, other: &Tree, n_taxa: usize) -> Result<RfDistance, TreeError> {
+        let shared = self.shared_taxa(other, n_taxa)?;
+        let a = self.restricted_splits(n_taxa, &shared, false);
+        let b = other.restricted_splits(n_taxa, &shared, false);
+
+        let common = a.iter().filter(|s| b.binary_search(s).is_ok()).count(); // Splits are sorted
+        let distance = a.len() + b.len() - 2 * common;
+        let n = shared.count();
+        let max = 2 * n.saturating_sub(3);
+
+        Ok(RfDistance {
+            distance,
+            max,
+            normalized: if max == 0 { 0.0 } else { distance as f64 / max as f64 },
+            shared_taxa: n,
+        })
+    }
+
+    // Branch-length weighted RF distance: the sum over all splits, pendant edges included, of the absolute
+    // difference between the split's edge length in the two trees (a split missing from a tree, or an
+    // edge without a length, counts as length 0).
+    pub fn weighted_robinson_foulds(&self, other: &Tree, n_taxa: usize) -> Result<f64, TreeError> {
+        let shared = self.shared_taxa(other, n_taxa)?;
+        let lengths = |splits: Vec<Split>| -> HashMap<Bitset, f64> {
+            splits.into_iter().map(|s| (s.bits, s.length.unwrap_or(0.0))).collect()
+        };
+        let a = lengths(self.restricted_splits(n_taxa, &shared, true));
+        let b = lengths(other.restricted_splits(n_taxa, &shared, true));
+
+        let only_b: f64 = b.iter().filter(|(bits, _)| !a.contains_key(*bits)).map(|(_, l)| l.abs()).sum();
+        let from_a: f64 = a.iter().map(|(bits, la)| (la - b.get(bits).copied().unwrap_or(0.0)).abs()).sum();
+        Ok(from_a + only_b)
+    }
+}
+
+// RF distances between every pair of trees, e.g. for clustering gene trees. Entry [i][j] compares
+// trees i and j on their shared taxa.
+pub fn pairwise_robinson_foulds(trees: &[Tree], n_taxa: usize) -> Result<Vec<Vec<RfDistance>>, TreeError> {
+    let empty = RfDistance { distance: 0, max: 0, normalized: 0.0, shared_taxa: 0 };
+    let mut matrix = vec![vec![empty; trees.len()]; trees.len()];
+
+    for i in 0..trees.len() {
+        matrix[i][i] = trees[i].robinson_foulds(&trees[i], n_taxa)?;
+        for j in i + 1..trees.len() {
+            let d = trees[i].robinson_foulds(&trees[j], n_taxa)?;
+            matrix[i][j] = d;
+            matrix[j][i] = d;
+        }
+    }
+    Ok(matrix)
+}
+
+// Result of a quartet comparison. Each of the C(n, 4) four-taxon subsets of the shared taxa falls in
+// exactly one category; a quartet is unresolved in a tree when its four leaves meet at a polytomy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct QuartetDistance {
+    pub shared_taxa: usize, // Number of taxa the comparison was made on
+    pub total: u64, // C(n, 4)
+    pub same: u64, // Resolved the same way in both trees
+    pub different: u64, // Resolved in both trees, but differently
+    pub unresolved_in_first: u64, // Unresolved in the first tree only
+    pub unresolved_in_second: u64, // Unresolved in the second tree only
+    pub unresolved_in_both: u64, // Unresolved in both trees
+}
+
+impl QuartetDistance {
+    // Number of quartets whose induced topologies differ (a resolved and an unresolved quartet differ).
+    pub fn distance(&self) -> u64 {
+        self.different + self.unresolved_in_first + self.unresolved_in_second
+    }
+
+    // Distance divided by the number of quartets (0 when there are fewer than four shared taxa).
+    pub fn normalized(&self) -> f64 {
+        if self.total == 0 { 0.0 } else { self.distance() as f64 / self.total as f64 }
+    }
+
+    // Distance counting only quartets resolved in both trees.
+    pub fn resolved_distance(&self) -> u64 {
+        self.different
+    }
+}
+
+// Undirected view of a tree used by the quartet distance: for a pair of leaves (a, b), every other shared
+// leaf x is labelled with where it hangs off the a–b path (the path node v and the neighbour w of v
+// leading to x). Then for a fourth leaf y: ab|xy iff x and y share w; the quartet is unresolved iff they
+// share v but not w; otherwise it is resolved as ax|by or ay|bx.
+struct PathLabeler<'a> {
+    tree: &'a Tree,
+    adjacency: Vec<Vec<usize>>, // Neighbours of every node
+    leaf_of: Vec<Option<usize>>, // Leaf carrying each taxon id
+    on_path: Vec<usize>, // Stamp marking nodes on the current path
+    stamp: usize,
+    path: Vec<usize>, // Nodes on the current path, reused between pairs
+    stack: Vec<(usize, usize)>, // (node, node it was reached from) while exploring, reused between pairs
+}
+
+impl<'a> PathLabeler<'a> {
+    fn new(tree: &'a Tree, n_taxa: usize) -> Self {
+        let mut adjacency = vec![Vec::new(); tree.nodes.len()];
+        for (id, node) in tree.nodes.iter().enumerate() {
+            if let Some(p) = node.parent {
+                adjacency[id].push(p);
+                adjacency[p].push(id);
+            }
+        }
+        PathLabeler {
+            tree,
+            adjacency,
+            leaf_of: tree.leaves_by_taxon(n_taxa),
+            on_path: vec![0; tree.nodes.len()],
+            stamp: 0,
+            path: Vec::new(),
+            stack: Vec::new(),
+        }
+    }
+
+    // Fills labels[x] = (v, w) for every taxon x in `taxa` other than a and b.
+    fn label(&mut self, a: usize, b: usize, labels: &mut [(usize, usize)]) {
+        let (la, lb) = (self.leaf_of[a].expect("shared taxon"), self.leaf_of[b].expect("shared taxon"));
+        let lca = self.tree.lca(la, lb).expect("nodes of the same tree");
+        self.stamp += 1;
+
+        let mut path = std::mem::take(&mut self.path); // Nodes on the a–b path
+        let mut stack = std::mem::take(&mut self.stack);
+        path.clear();
+        for start in [la, lb] {
+            let mut node = start;
+            while node != lca {
+                path.push(node);
+                node = self.tree.nodes[node].parent.expect("below the LCA");
+            }
+        }
+        path.push(lca);
+        for &v in &path {
+            self.on_path[v] = self.stamp;
+        }
+
+        for &v in &path { // Explore every subtree hanging off the path
+            for &w in &self.adjacency[v] {
+                if self.on_path[w] == self.stamp {
+                    continue;
+                }
+                stack.push((w, v));
+                while let Some((node, from)) = stack.pop() {
+                    if let Some(t) = self.tree.nodes[node].taxon
+                        && t < labels.len() {
+                        labels[t] = (v, w);
+                    }
+                    for &next in &self.adjacency[node] {
+                        if next != from {
+                            stack.push((next, node));
+                        }
+                    }
+                }
+            }
+        }
+        self.path = path;
+        self.stack = stack;
+    }
+
+    // Number of quartets of the shared taxa that are unresolved (star-shaped) in the tree: at every node
+    // of degree four or more, choose four leaves from four different components around it. Uses the
+    // subtree leaf counts so no quartet is enumerated.
+    fn unresolved_quartets(&self, shared: &Bitset) -> u64 {
+        let tree = self.tree;
+        let counts: Vec<u64> = if tree.taxon_bitset(shared.width()) == *shared && tree.leaves().iter().all(|&l| tree.nodes[l].taxon.is_some()) {
+            tree.compute_subtree_leaf_counts().into_iter().map(|c| c as u64).collect() // Every leaf is a shared taxon
+        } else {
+            tree.clade_bitsets(shared.width()).into_iter().map(|mut c| { c.intersect_with(shared); c.count() as u64 }).collect()
+        };
+        let total = counts[tree.root];
+
+        let mut star = 0;
+        for (id, node) in tree.nodes.iter().enumerate() {
+            let mut sizes: Vec<u64> = node.children.iter().map(|&c| counts[c]).collect();
+            if node.parent.is_some() {
+                sizes.push(total - counts[id]); // Component above the node
+            }
+            if sizes.iter().filter(|&&s| s > 0).count() < 4 {
+                continue;
+            }
+            let mut e = [1u64, 0, 0, 0, 0]; // Elementary symmetric polynomials of the component sizes
+            for s in sizes {
+                for j in (1..=4).rev() {
+                    e[j] += e[j - 1] * s;
+                }
+            }
+            star += e[4];
+        }
+        star
+    }
+}
+
+const NONE: usize = usize::MAX; // End of a bucket chain in PairCounter
+
+// Reusable buffers for counting the pairs of taxa that agree on a key made of two node ids. Taxa are chained
+// into buckets by the first id, then each bucket is counted by the second id, so a call is linear in the
+// number of taxa and neither hashes nor allocates.
+struct PairCounter {
+    head: Vec<usize>, // First taxon of the bucket of each first id, or NONE
+    next: Vec<usize>, // Next taxon in the same bucket, or NONE
+    seen: Vec<u64>, // Taxa of the current bucket seen so far, by second id
+    buckets: Vec<usize>, // First ids with a non-empty bucket
+}
+
+impl PairCounter {
+    fn new(n_nodes: usize, n_taxa: usize) -> Self {
+        PairCounter { head: vec![NONE; n_nodes], next: vec![NONE; n_taxa], seen: vec![0; n_nodes], buckets: Vec::new() }
+    }
+
+    // Number of pairs {x, y} of `taxa` with key(x) == key(y). Keys are node ids below n_nodes.
+    fn count(&mut self, taxa: &[usize], key: impl Fn(usize) -> (usize, usize)) -> u64 {
+        for &x in taxa {
+            let (k, _) = key(x);
+            if self.head[k] == NONE {
+                self.buckets.push(k);
+            }
+            self.next[x] = self.head[k];
+            self.head[k] = x;
+        }
+
+        let mut pairs = 0;
+        for &k in &self.buckets {
+            let mut x = self.head[k];
+            while x != NONE { // Each taxon pairs with the ones before it that share the second id
+                let second = key(x).1;
+                pairs += self.seen[second];
+                self.seen[second] += 1;
+                x = self.next[x];
+            }
+            let mut x = self.head[k];
+            while x != NONE { // Clear the counts for the next bucket
+                self.seen[key(x).1] = 0;
+                x = self.next[x];
+            }
+            self.head[k] = NONE;
+        }
+        self.buckets.clear();
+        pairs
+    }
+}
+
+impl Tree {
+    // Quartet distance to another tree on their shared taxa. For each of the O(n²) pairs of taxa, every
+    // other taxon is located relative to the pair's path in both trees in linear time, which classifies all
+    // quartets containing the pair at once; the overall cost is O(n³) time and O(n) memory (all buffers are
+    // reused between pairs) rather than enumerating O(n⁴) quartets.
+    pub fn quartet_distance(&self, other: &Tree, n_taxa: usize) -> Result<QuartetDistance, TreeError> {
+        let shared = self.shared_taxa(other, n_taxa)?;
+        let taxa: Vec<usize> = shared.iter().collect();
+        let n = taxa.len() as u64;
+        let mut result = QuartetDistance {
+            shared_taxa: taxa.len(),
+            total: n * n.saturating_sub(1) * n.saturating_sub(2) * n.saturating_sub(3) / 24,
+            ..QuartetDistance::default()
+        };
+        if taxa.len() < 4 {
+            return Ok(result);
+        }
+
+        let mut first = PathLabeler::new(self, n_taxa);
+        let mut second = PathLabeler::new(other, n_taxa);
+        let mut labels1 = vec![(0, 0); n_taxa];
+        let mut labels2 = vec![(0, 0); n_taxa];
+        let mut counter = PairCounter::new(self.nodes.len().max(other.nodes.len()), n_taxa);
+        let mut others = Vec::with_capacity(taxa.len());
+        let (mut same, mut different, mut star_in_second, mut star_in_first) = (0, 0, 0, 0);
+
+        for (i, &a) in taxa.iter().enumerate() {
+            for &b in &taxa[i + 1..] {
+                first.label(a, b, &mut labels1);
+                second.label(a, b, &mut labels2);
+
+                // Count the pairs of other taxa that hang off the a–b path at the same place in each tree.
+                others.clear();
+                others.extend(taxa.iter().copied().filter(|&x| x != a && x != b));
+                let (l1, l2) = (&labels1, &labels2);
+                let ab_first = counter.count(&others, |x| (l1[x].1, 0)); // x, y on the same side of a–b in the first tree
+                let ab_both = counter.count(&others, |x| (l1[x].1, l2[x].1)); // ... in both trees
+                let first_same_v = counter.count(&others, |x| (l1[x].1, l2[x].0)); // ... and meeting the path at the same node in the second
+                let second_same_v = counter.count(&others, |x| (l2[x].1, l1[x].0)); // Same side in the second, same node in the first
+
+                same += ab_both; // ab|xy in both trees
+                different += ab_first - first_same_v; // ab|xy in the first, a different resolution in the second
+                star_in_second += first_same_v - ab_both; // ab|xy in the first, star in the second
+                star_in_first += second_same_v - ab_both; // ab|xy in the second, star in the first
+            }
+        }
+
+        // Every resolved quartet ab|cd was counted once from {a, b} and once from {c, d}.
+        result.same = same / 2;
+        result.different = different / 2;
+        result.unresolved_in_second = star_in_second / 2;
+        result.unresolved_in_first = star_in_first / 2;
+        result.unresolved_in_both = first.unresolved_quartets(&shared) - result.unresolved_in_first;
+        Ok(result)
+    }
+}
@@ -0,0 +1,101 @@
+// This module builds consensus trees from a collection of gene trees bound to one TaxonSet, using the
+// frequencies of their non-trivial splits:
+// - strict: splits present in every tree;
+// - majority rule: splits present in more than a given fraction (at least one half) of the trees;
+// - greedy (extended majority): splits added by decreasing frequency as long as they are compatible with
+//   those already accepted, which resolves further than majority rule.
+// Every internal edge of the result carries the frequency of its split as support. Consensus trees are
+// unrooted: the root is a polytomy holding the lowest taxon, and they have no branch lengths.
+use super::operations::TreeError;
+use super::splits::{Bitset, Split};
+use super::structure::{Tree, Node, NodeId};
+use super::taxa::TaxonSet;
+use std::collections::HashMap;
+
+// Splits of all trees with their frequencies, after checking the trees share one leaf set.
+fn split_support(trees: &[Tree], n_taxa: usize) -> Result<(Bitset, Vec<(Split, usize)>), TreeError> {
+    let first = trees.first().ok_or(TreeError::NoTrees)?;
+    let all = first.taxon_bitset(n_taxa);
+    if all.is_empty() {
+        return Err(TreeError::EmptyTaxonSet);
+    }
+    if trees.iter().any(|t| t.taxon_bitset(n_taxa) != all) {
+        return Err(TreeError::DifferentTaxa);
+    }
+
+    let mut counts: HashMap<Bitset, usize> = HashMap::new();
+    for tree in trees {
+        for split in tree.splits(n_taxa) {
+            *counts.entry(split.bits).or_insert(0) += 1;
+        }
+    }
+    let mut splits: Vec<(Split, usize)> = counts.into_iter()
+        .map(|(bits, count)| (Split { bits, length: None, support: Some(count as f64 / trees.len() as f64) }, count))
+        .collect();
+    splits.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0))); // Most frequent first, deterministic ties
+    Ok((all, splits))
+}
+
+// Builds the tree displaying a set of pairwise compatible splits. Their canonical sides all avoid the
+// lowest taxon, so any two are nested or disjoint and form a hierarchy under a root holding that taxon.
+fn tree_from_splits(mut splits: Vec<Split>, all: &Bitset, taxa: &TaxonSet) -> Tree {
+    splits.sort_by_key(|s| std::cmp::Reverse(s.size())); // Larger clades before the clades they contain
+    let mut tree = Tree::new();
+    tree.nodes.push(Node::default()); // Root
+    let mut clades: Vec<(NodeId, &Bitset)> = Vec::new(); // Internal nodes created so far, largest first
+
+    // The parent of a clade or leaf is the smallest clade already placed that contains it.
+    let parent_of = |clades: &[(NodeId, &Bitset)], contains: &dyn Fn(&Bitset) -> bool| {
+        clades.iter().rev().find(|(_, bits)| contains(bits)).map_or(0, |&(id, _)| id)
+    };
+
+    for split in &splits {
+        let parent = parent_of(&clades, &|bits: &Bitset| split.bits.is_subset(bits));
+        let id = tree.nodes.len();
+        tree.nodes.push(Node { parent: Some(parent), support: split.support, ..Default::default() });
+        tree.nodes[parent].children.push(id);
+        clades.push((id, &split.bits));
+    }
+    for taxon in all.iter() {
+        let parent = parent_of(&clades, &|bits: &Bitset| bits.contains(taxon));
+        let id = tree.nodes.len();
+        let label = taxa.name(taxon).map(str::to_string);
+        tree.nodes.push(Node { parent: Some(parent), label, taxon: Some(taxon), ..Default::default() });
+        tree.nodes[parent].children.push(id);
+    }
+    tree
+}
+
+// Consensus of the splits present in every tree.
+pub fn strict_consensus(trees: &[Tree], taxa: &TaxonSet) -> Result<Tree, TreeError> {
+    let (all, splits) = split_support(trees, taxa.len())?;
+    let kept = splits.into_iter().filter(|&(_, c)| c == trees.len()).map(|(s, _)| s).collect();
+    Ok(tree_from_splits(kept, &all, taxa))
+}
+
+// Majority-rule consensus: splits found in more than `threshold` of the trees, for a threshold in
+// [0.5, 1). Lower thresholds could keep conflicting splits; 0.5 gives the classic majority-rule tree.
+pub fn majority_consensus(trees: &[Tree], taxa: &TaxonSet, threshold: f64) -> Result<Tree, TreeError> {
+    if !(0.5..1.0).contains(&threshold) {
+        return Err(TreeError::InvalidFraction(threshold));
+    }
+    let (all, splits) = split_support(trees, taxa.len())?;
+    let kept = splits.into_iter()
+        .filter(|&(_, c)| c as f64 > threshold * trees.len() as f64)
+        .map(|(s, _)| s)
+        .collect();
+    Ok(tree_from_splits(kept, &all, taxa))
+}
+
+// Greedy (extended majority-rule) consensus: splits in decreasing order of frequency, each kept if it is
+// compatible with every split kept before it. Ties are broken by split bitset, so the result is deterministic.
+pub fn greedy_consensus(trees: &[Tree], taxa: &TaxonSet) -> Result<Tree, TreeError> {
+    let (all, splits) = split_support(trees, taxa.len())?;
+    let mut kept: Vec<Split> = Vec::new();
+    for (split, _) in splits {
+        if kept.iter().all(|k| k.is_compatible(&split, &all)) {
+            kept.push(split);
+        }
+    }
+    Ok(tree_from_splits(kept, &all, taxa))
+}
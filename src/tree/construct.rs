@@ -0,0 +1,188 @@
+// This module builds trees from distance matrices: neighbor joining for unrooted trees with unequal
+// rates, and UPGMA for rooted ultrametric trees. Leaves are labelled with the matrix names in matrix
+// order; call bind_taxa on the result to attach taxon ids. Negative branch lengths, which NJ produces on
+// non-additive data, are set to zero and the difference is given to the sibling edge.
+use super::distance::DistanceMatrix;
+use super::operations::TreeError;
+use super::structure::{Tree, Node, NodeId};
+
+// Starts a tree with one leaf per matrix row and checks the matrix can be used.
+fn leaves_of(matrix: &DistanceMatrix) -> Result<Tree, TreeError> {
+    if matrix.is_empty() {
+        return Err(TreeError::EmptyTaxonSet);
+    }
+    if !matrix.is_complete() {
+        return Err(TreeError::IncompleteMatrix);
+    }
+    let n = matrix.len();
+    for i in 0..n { // Infinite entries, or rows whose sum overflows, leave no sensible pair to join
+        let row: Vec<f64> = (0..n).filter_map(|j| matrix.get(i, j)).collect();
+        if let Some(&d) = row.iter().find(|d| d.is_infinite()) {
+            return Err(TreeError::InvalidDistance(d));
+        }
+        let sum: f64 = row.iter().sum();
+        if !sum.is_finite() {
+            return Err(TreeError::InvalidDistance(sum));
+        }
+    }
+    let mut tree = Tree::new();
+    for name in matrix.names() {
+        tree.nodes.push(Node { label: Some(name.clone()), ..Default::default() });
+    }
+    Ok(tree)
+}
+
+// Adds an internal node above the given children with the given edge lengths, returning its id.
+fn join(tree: &mut Tree, children: &[(NodeId, f64)]) -> NodeId {
+    let id = tree.nodes.len();
+    tree.nodes.push(Node { children: children.iter().map(|&(c, _)| c).collect(), ..Default::default() });
+    for &(child, length) in children {
+        tree.nodes[child].parent = Some(id);
+        tree.nodes[child].length_to_parent = Some(length);
+    }
+    id
+}
+
+// Splits a pair distance into two non-negative branch lengths, moving any negative part to the other edge.
+fn non_negative(a: f64, b: f64) -> (f64, f64) {
+    match (a < 0.0, b < 0.0) {
+        (true, _) => (0.0, a + b),
+        (_, true) => (a + b, 0.0),
+        _ => (a, b),
+    }
+}
+
+// Neighbor joining (Saitou & Nei 1987, Studier & Keppler 1988). Returns an unrooted tree whose root is a
+// trifurcation (a bifurcation for two taxa).
+//
+// The pair minimising Q(a, b) = (r − 2)·d(a, b) − R(a) − R(b) is found with the bounded search of
+// RapidNJ (Simonsen et al. 2008): every row keeps its distances sorted, and since R(b) ≤ max R, a row can
+// be abandoned as soon as (r − 2)·d − R(a) − max R reaches the best Q found so far. This visits a small
+// part of each row on typical data, so thousands of taxa take seconds instead of the O(n³) of the plain
+// method, at the cost of O(n²) memory for the sorted rows. Merged clusters reuse the slot of one of their
+// parts; sorted entries whose slot has since been reused are recognised by their stale distance.
+pub fn neighbor_joining(matrix: &DistanceMatrix) -> Result<Tree, TreeError> {
+    let mut tree = leaves_of(matrix)?;
+    let n = matrix.len();
+    let dist = |i: usize, j: usize| matrix.get(i, j).expect("matrix is complete");
+    match n {
+        1 => return Ok(tree),
+        2 => {
+            let half = dist(0, 1) / 2.0;
+            tree.root = join(&mut tree, &[(0, half), (1, half)]);
+            return Ok(tree);
+        }
+        _ => {}
+    }
+
+    let mut d: Vec<f64> = (0..n * n).map(|k| dist(k / n, k % n)).collect(); // Working copy, n x n
+    let mut node_of: Vec<NodeId> = (0..n).collect(); // Tree node currently held by each slot
+    let mut active = vec![true; n];
+    let mut row_sum: Vec<f64> = (0..n).map(|i| d[i * n..(i + 1) * n].iter().sum()).collect();
+    let sorted_row = |d: &[f64], active: &[bool], i: usize| -> Vec<(f64, usize)> {
+        let mut row: Vec<(f64, usize)> = (0..n).filter(|&k| k != i && active[k]).map(|k| (d[i * n + k], k)).collect();
+        row.sort_by(|a, b| a.0.total_cmp(&b.0));
+        row
+    };
+    let mut rows: Vec<Vec<(f64, usize)>> = (0..n).map(|i| sorted_row(&d, &active, i)).collect();
+
+    for r in (4..=n).rev() { // r = number of clusters left
+        let scale = (r - 2) as f64;
+        let max_sum = (0..n).filter(|&k| active[k]).map(|k| row_sum[k]).fold(f64::NEG_INFINITY, f64::max);
+        let mut best: Option<(f64, (usize, usize))> = None; // Seeded by the first valid pair, so overflowing Q values cannot leave it unset
+        for a in (0..n).filter(|&a| active[a]) {
+            for &(dab, b) in &rows[a] {
+                if best.is_some_and(|(q, _)| scale * dab - row_sum[a] - max_sum >= q) {
+                    break; // No later entry of this row can beat the best pair
+                }
+                if !active[b] || d[a * n + b] != dab {
+                    continue; // Slot b was merged away or now holds another cluster
+                }
+                let q = scale * dab - row_sum[a] - row_sum[b];
+                if best.is_none_or(|(best_q, _)| q < best_q) {
+                    best = Some((q, (a, b)));
+                }
+            }
+        }
+
+        let (_, (i, j)) = best.expect("at least two active clusters");
+        let dij = d[i * n + j];
+        let li = dij / 2.0 + (row_sum[i] - row_sum[j]) / (2.0 * scale);
+        let (li, lj) = non_negative(li, dij - li);
+        let u = join(&mut tree, &[(node_of[i], li), (node_of[j], lj)]);
+
+        // The new cluster takes slot i; slot j is retired.
+        active[j] = false;
+        row_sum[i] = 0.0;
+        for k in (0..n).filter(|&k| active[k] && k != i) {
+            let dku = (d[k * n + i] + d[k * n + j] - dij) / 2.0;
+            row_sum[k] += dku - d[k * n + i] - d[k * n + j];
+            row_sum[i] += dku;
+            d[k * n + i] = dku;
+            d[i * n + k] = dku;
+        }
+        node_of[i] = u;
+        rows[i] = sorted_row(&d, &active, i);
+        rows[j] = Vec::new();
+    }
+
+    // Join the last three clusters at the root.
+    let last: Vec<usize> = (0..n).filter(|&k| active[k]).collect();
+    let (a, b, c) = (last[0], last[1], last[2]);
+    let (dab, dac, dbc) = (d[a * n + b], d[a * n + c], d[b * n + c]);
+    let lengths = [(dab + dac - dbc) / 2.0, (dab + dbc - dac) / 2.0, (dac + dbc - dab) / 2.0];
+    let children: Vec<(NodeId, f64)> = [a, b, c].iter().zip(lengths).map(|(&s, l)| (node_of[s], l.max(0.0))).collect();
+    tree.root = join(&mut tree, &children);
+    Ok(tree)
+}
+
+// UPGMA (average linkage). Returns a rooted ultrametric tree: the root-to-leaf distance is half the
+// distance of the last pair joined, and every node sits at half the average distance between the two
+// clusters it joins. Each slot caches its nearest neighbour, so only slots whose neighbour was merged are
+// rescanned, which makes the usual case close to O(n²).
+pub fn upgma(matrix: &DistanceMatrix) -> Result<Tree, TreeError> {
+    let mut tree = leaves_of(matrix)?;
+    let n = matrix.len();
+    let mut d: Vec<f64> = (0..n * n).map(|k| matrix.get(k / n, k % n).expect("matrix is complete")).collect();
+    let mut node_of: Vec<NodeId> = (0..n).collect();
+    let mut active = vec![true; n];
+    let mut size = vec![1usize; n];
+    let mut height = vec![0.0; n];
+
+    let nearest = |d: &[f64], active: &[bool], i: usize| -> usize {
+        (0..n).filter(|&k| k != i && active[k]).min_by(|&a, &b| d[i * n + a].total_cmp(&d[i * n + b])).unwrap_or(i)
+    };
+    let mut nn: Vec<usize> = (0..n).map(|i| nearest(&d, &active, i)).collect();
+
+    for _ in 1..n {
+        let i = (0..n).filter(|&k| active[k])
+            .min_by(|&a, &b| d[a * n + nn[a]].total_cmp(&d[b * n + nn[b]]))
+            .expect("at least two clusters left");
+        let j = nn[i];
+        let h = d[i * n + j] / 2.0;
+        let u = join(&mut tree, &[(node_of[i], (h - height[i]).max(0.0)), (node_of[j], (h - height[j]).max(0.0))]);
+
+        // The new cluster takes slot i; distances are size-weighted averages of its two parts.
+        active[j] = false;
+        for k in (0..n).filter(|&k| active[k] && k != i) {
+            let dku = (size[i] as f64 * d[k * n + i] + size[j] as f64 * d[k * n + j]) / (size[i] + size[j]) as f64;
+            d[k * n + i] = dku;
+            d[i * n + k] = dku;
+        }
+        size[i] += size[j];
+        height[i] = h;
+        node_of[i] = u;
+
+        nn[i] = nearest(&d, &active, i);
+        for k in (0..n).filter(|&k| active[k] && k != i) {
+            if nn[k] == i || nn[k] == j {
+                nn[k] = nearest(&d, &active, k); // Its neighbour moved away or disappeared
+            } else if d[k * n + i] < d[k * n + nn[k]] {
+                nn[k] = i;
+            }
+        }
+    }
+
+    tree.root = node_of[(0..n).find(|&k| active[k]).expect("one cluster left")];
+    Ok(tree)
+}
@@ -0,0 +1,204 @@
+// This module computes leaf-by-leaf distance matrices, the input of distance-based methods such as
+// neighbor joining, NeighborNet or NANUQ. Rows and columns follow the ids of a TaxonSet, so matrices of
+// different gene trees line up and can be averaged; taxa absent from a tree are left missing.
+use super::structure::{Tree, NodeId};
+use super::taxa::TaxonSet;
+use std::io::{self, Write};
+
+// Symmetric matrix of distances between named taxa. Missing entries (taxa that never co-occur) are None.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DistanceMatrix {
+    names: Vec<String>, // Row and column names, in TaxonSet id order
+    values: Vec<f64>, // Row-major n x n values, NaN for missing entries
+}
+
+impl DistanceMatrix {
+    // Matrix over the given names with every off-diagonal entry missing and zeros on the diagonal.
+    pub fn new(names: Vec<String>) -> Self {
+        let n = names.len();
+        let mut values = vec![f64::NAN; n * n];
+        for i in 0..n {
+            values[i * n + i] = 0.0;
+        }
+        DistanceMatrix { names, values }
+    }
+
+    pub fn len(&self) -> usize { // Number of taxa
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    pub fn get(&self, i: usize, j: usize) -> Option<f64> {
+        let value = self.values[i * self.len() + j];
+        (!value.is_nan()).then_some(value)
+    }
+
+    pub fn set(&mut self, i: usize, j: usize, value: f64) { // Sets both (i, j) and (j, i)
+        let n = self.len();
+        self.values[i * n + j] = value;
+        self.values[j * n + i] = value;
+    }
+
+    pub fn is_complete(&self) -> bool { // No missing entries
+        !self.values.iter().any(|v| v.is_nan())
+    }
+
+    // Writes the matrix in (relaxed) PHYLIP format: the number of taxa, then one row per taxon starting
+    // with its name padded to at least 10 characters. Fails with InvalidData if an entry is missing, as
+    // PHYLIP has no notation for it.
+    pub fn write_phylip<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        if !self.is_complete() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "distance matrix has missing entries"));
+        }
+        let width = self.names.iter().map(|name| name.chars().count() + 1).max().unwrap_or(0).max(10);
+        writeln!(out, "{}", self.len())?;
+        for (i, name) in self.names.iter().enumerate() {
+            write!(out, "{:<width$}", name, width = width)?;
+            let row: Vec<String> = (0..self.len()).map(|j| self.values[i * self.len() + j].to_string()).collect();
+            writeln!(out, "{}", row.join(" "))?;
+        }
+        Ok(())
+    }
+
+    pub fn to_phylip(&self) -> io::Result<String> {
+        let mut out = Vec::new();
+        self.write_phylip(&mut out)?;
+        Ok(String::from_utf8(out).expect("PHYLIP output is UTF-8"))
+    }
+}
+
+// Leaf-by-leaf distance matrices
+impl Tree {
+    // Sums of branch lengths between all pairs of leaves, in the order of `taxa`. The tree must be bound to
+    // `taxa`; rows of taxa absent from the tree stay missing. Missing branch lengths count as 0.
+    pub fn patristic_matrix(&self, taxa: &TaxonSet) -> DistanceMatrix {
+        self.leaf_distance_matrix(taxa, |tree, node| tree.nodes[node].length_to_parent.unwrap_or(0.0))
+    }
+
+    // Numbers of edges between all pairs of leaves, in the order of `taxa`.
+    pub fn topological_matrix(&self, taxa: &TaxonSet) -> DistanceMatrix {
+        self.leaf_distance_matrix(taxa, |_, _| 1.0)
+    }
+
+    // Fills the matrix in a single pass: the leaves of every subtree form a contiguous range of the
+    // preorder leaf sequence, and each pair of leaves is visited exactly once, at its LCA, where its
+    // distance is dist(x) + dist(y) − 2·dist(lca) with dist measured from the root. O(n²) overall.
+    fn leaf_distance_matrix<F: Fn(&Tree, NodeId) -> f64>(&self, taxa: &TaxonSet, edge_weight: F) -> DistanceMatrix {
+        let mut matrix = DistanceMatrix::new(taxa.names().to_vec());
+        if self.nodes.is_empty() {
+            return matrix;
+        }
+
+        let mut from_root = vec![0.0; self.nodes.len()];
+        let mut leaves: Vec<(NodeId, usize)> = Vec::new(); // (leaf, taxon id) in preorder
+        for node in self.preorder_iter() {
+            if let Some(parent) = self.nodes[node].parent {
+                from_root[node] = from_root[parent] + edge_weight(self, node);
+            }
+            if let (true, Some(t)) = (self.is_leaf(node), self.nodes[node].taxon) {
+                leaves.push((node, t));
+            }
+        }
+
+        let mut range = vec![(0, 0); self.nodes.len()]; // Range of each subtree in `leaves`
+        let mut next = 0;
+        for node in self.postorder_iter() {
+            if self.is_leaf(node) {
+                let bound = self.nodes[node].taxon.is_some() as usize;
+                range[node] = (next, next + bound);
+                next += bound;
+            } else {
+                let children = &self.nodes[node].children;
+                range[node] = (range[children[0]].0, range[children[children.len() - 1]].1);
+            }
+        }
+
+        for node in self.postorder_iter() {
+            let children = &self.nodes[node].children;
+            for (k, &a) in children.iter().enumerate() {
+                for &b in &children[k + 1..] {
+                    for &(x, tx) in &leaves[range[a].0..range[a].1] {
+                        for &(y, ty) in &leaves[range[b].0..range[b].1] {
+                            matrix.set(tx, ty, from_root[x] + from_root[y] - 2.0 * from_root[node]);
+                        }
+                    }
+                }
+            }
+        }
+        matrix
+    }
+}
+
+// Running entry-wise mean of matrices over the same names, skipping missing entries.
+struct MatrixMean {
+    names: Vec<String>,
+    sums: Vec<f64>,
+    counts: Vec<usize>, // Number of matrices where each entry was present
+}
+
+impl MatrixMean {
+    fn new(names: Vec<String>) -> Self {
+        let cells = names.len() * names.len();
+        MatrixMean { names, sums: vec![0.0; cells], counts: vec![0; cells] }
+    }
+
+    fn add(&mut self, matrix: &DistanceMatrix) -> bool { // False if the matrix is over other names
+        if matrix.names != self.names {
+            return false;
+        }
+        for (k, &value) in matrix.values.iter().enumerate() {
+            if !value.is_nan() {
+                self.sums[k] += value;
+                self.counts[k] += 1;
+            }
+        }
+        true
+    }
+
+    fn finish(self) -> DistanceMatrix {
+        let values = self.sums.iter().zip(&self.counts)
+            .map(|(&sum, &count)| if count > 0 { sum / count as f64 } else { f64::NAN })
+            .collect();
+        DistanceMatrix { names: self.names, values }
+    }
+}
+
+// Averages matrices over the same taxa entry by entry, using only the matrices where the entry is present,
+// so a pair is averaged over the gene trees containing both taxa. Pairs present in none stay missing.
+// Returns None if there are no matrices or they do not share the same names.
+pub fn average_matrices<'a, I>(matrices: I) -> Option<DistanceMatrix>
+where
+    I: IntoIterator<Item = &'a DistanceMatrix>,
+{
+    let mut matrices = matrices.into_iter().peekable();
+    let mut mean = MatrixMean::new(matrices.peek()?.names.clone());
+    for matrix in matrices {
+        if !mean.add(matrix) {
+            return None;
+        }
+    }
+    Some(mean.finish())
+}
+
+// Average patristic distance matrix of gene trees bound to `taxa` (see average_matrices). Matrices are
+// computed one tree at a time, so only one is held in memory besides the running sums. None if there
+// are no trees.
+pub fn average_patristic_matrix<'a, I>(trees: I, taxa: &TaxonSet) -> Option<DistanceMatrix>
+where
+    I: IntoIterator<Item = &'a Tree>,
+{
+    let mut mean = MatrixMean::new(taxa.names().to_vec());
+    let mut any = false;
+    for tree in trees {
+        mean.add(&tree.patristic_matrix(taxa));
+        any = true;
+    }
+    any.then(|| mean.finish())
+}
@@ -0,0 +1,266 @@
+// This module provides in-place topology moves for tree search: detaching a subtree, grafting it onto an
+// edge, nearest-neighbor interchange (NNI) and subtree prune-and-regraft (SPR). Every move keeps parent
+// and children links consistent, merges or splits branch lengths like suppress_unary and reroot_on_edge
+// do, and returns an Edit that Tree::undo reverts exactly.
+//
+// Node ids are never renumbered by these moves, so records stay valid across a sequence of edits; undo
+// them in reverse order (last edit first). Nodes removed from the tree stay in the arena, unreachable, and
+// an SPR reuses the node freed by its own prune. Call compact() once the edits are final (this invalidates
+// pending Edit records).
+use super::operations::TreeError;
+use super::structure::{Tree, Node, NodeId};
+
+// Undo record of one move.
+#[derive(Debug, Clone)]
+pub enum Edit {
+    Detach(Detached),
+    Graft(Grafted),
+    Nni { child: NodeId, sibling: NodeId }, // The two nodes that were swapped
+    Spr(Detached, Grafted),
+}
+
+// State needed to put a detached subtree back where it was.
+#[derive(Debug, Clone)]
+pub struct Detached {
+    node: NodeId, // Root of the detached subtree
+    parent: NodeId, // Its former parent
+    slot: usize, // Its former index among the parent's children
+    merged: Option<Merged>, // Set when the parent was left with one child and suppressed
+}
+
+#[derive(Debug, Clone)]
+struct Merged {
+    sibling: NodeId, // The remaining child that took the parent's place
+    sibling_edge: (Option<f64>, Option<f64>), // Length and support of the sibling's edge before merging
+    parent_edge: (Option<f64>, Option<f64>), // Length and support of the parent's edge before merging
+    grandparent: Option<NodeId>, // None when the parent was the root
+    grand_slot: usize, // Index of the parent among the grandparent's children
+}
+
+impl Detached {
+    pub fn node(&self) -> NodeId { // Root of the detached subtree
+        self.node
+    }
+
+    pub fn freed(&self) -> Option<NodeId> { // The suppressed former parent, now unreachable, if any
+        self.merged.as_ref().map(|_| self.parent)
+    }
+}
+
+// State needed to remove a grafted subtree again.
+#[derive(Debug, Clone)]
+pub struct Grafted {
+    inserted: NodeId, // Node created on the target edge, now the parent of the subtree
+    subtree: NodeId, // Root of the grafted subtree
+    edge_child: NodeId, // Lower end of the target edge
+    edge_length: Option<f64>, // Length of the target edge before it was split
+    reused: Option<Node>, // Former content of a reused arena slot; None when the node was pushed
+}
+
+impl Grafted {
+    pub fn inserted(&self) -> NodeId { // The node inserted on the target edge
+        self.inserted
+    }
+}
+
+// Topology moves
+impl Tree {
+    // Removes the edge above `node`, leaving the subtree in the arena with no parent; the subtree keeps the
+    // length and support of the removed edge for a later graft. A parent left with a single child is
+    // suppressed (its two edges merged); if that parent was the root, the remaining child becomes the root.
+    pub fn detach(&mut self, node: NodeId) -> Result<Edit, TreeError> {
+        self.detach_subtree(node).map(Edit::Detach)
+    }
+
+    // Attaches a detached subtree onto the edge above `edge_child`, through a new node placed at `fraction`
+    // of the edge length measured from `edge_child` (see reroot_on_edge). Grafting above the root inserts a
+    // new root with the old root and the subtree as children.
+    pub fn graft(&mut self, subtree: NodeId, edge_child: NodeId, fraction: f64) -> Result<Edit, TreeError> {
+        self.graft_subtree(subtree, edge_child, fraction, None).map(Edit::Graft)
+    }
+
+    // Nearest-neighbor interchange across the edge between u = parent(child) and its parent: `child` (a
+    // child of u) and `sibling` (another child of u's parent) trade places. Both keep their edge lengths.
+    pub fn nni(&mut self, child: NodeId, sibling: NodeId) -> Result<Edit, TreeError> {
+        let n = self.nodes.len();
+        let (Some(u), true) = (self.nodes.get(child).and_then(|c| c.parent), sibling < n) else {
+            return Err(TreeError::InvalidNode(child));
+        };
+        let p = self.nodes[u].parent.ok_or(TreeError::InvalidNode(child))?;
+        if sibling == u || self.nodes[sibling].parent != Some(p) {
+            return Err(TreeError::InvalidNode(sibling));
+        }
+        self.swap_subtrees(child, sibling);
+        Ok(Edit::Nni { child, sibling })
+    }
+
+    // All NNI moves across the edge above `node`, as (child, sibling) pairs for nni. Two per edge in a
+    // binary tree; none for leaves and the root.
+    pub fn nni_moves(&self, node: NodeId) -> Vec<(NodeId, NodeId)> {
+        let Some(p) = self.nodes.get(node).and_then(|n| n.parent) else {
+            return Vec::new();
+        };
+        let siblings: Vec<NodeId> = self.nodes[p].children.iter().copied().filter(|&s| s != node).collect();
+        self.nodes[node].children.iter()
+            .flat_map(|&c| siblings.iter().map(move |&s| (c, s)))
+            .collect()
+    }
+
+    // Subtree prune and regraft: detaches the subtree above `subtree` and grafts it onto the edge above
+    // `edge_child` at `fraction`. The target must lie outside the subtree and must not be the subtree's
+    // current parent if that parent is suppressed by the prune (use the sibling's edge instead).
+    pub fn spr(&mut self, subtree: NodeId, edge_child: NodeId, fraction: f64) -> Result<Edit, TreeError> {
+        if edge_child >= self.nodes.len() || self.ancestors(edge_child).contains(&subtree) {
+            return Err(TreeError::InvalidNode(edge_child));
+        }
+        if !(0.0..=1.0).contains(&fraction) {
+            return Err(TreeError::InvalidFraction(fraction));
+        }
+        let detached = self.detach_subtree(subtree)?;
+        match self.graft_subtree(subtree, edge_child, fraction, detached.freed()) {
+            Ok(grafted) => Ok(Edit::Spr(detached, grafted)),
+            Err(err) => {
+                self.undo_detach(detached); // Leave the tree as it was
+                Err(err)
+            }
+        }
+    }
+
+    // Reverts a move. Edits must be undone in the reverse order they were made.
+    pub fn undo(&mut self, edit: Edit) {
+        self.lca_index = None;
+        match edit {
+            Edit::Detach(detached) => self.undo_detach(detached),
+            Edit::Graft(grafted) => self.undo_graft(grafted),
+            Edit::Nni { child, sibling } => self.swap_subtrees(sibling, child),
+            Edit::Spr(detached, grafted) => {
+                self.undo_graft(grafted);
+                self.undo_detach(detached);
+            }
+        }
+    }
+
+    fn detach_subtree(&mut self, node: NodeId) -> Result<Detached, TreeError> {
+        if node >= self.nodes.len() || node == self.root {
+            return Err(TreeError::InvalidNode(node));
+        }
+        let parent = self.nodes[node].parent.ok_or(TreeError::InvalidNode(node))?;
+        if self.nodes[parent].children.len() < 2 {
+            return Err(TreeError::InvalidNode(node)); // The parent would become a leaf
+        }
+        self.lca_index = None;
+
+        let slot = self.nodes[parent].children.iter().position(|&c| c == node).expect("child of its parent");
+        self.nodes[parent].children.remove(slot);
+        self.nodes[node].parent = None;
+
+        let mut merged = None;
+        if let [sibling] = self.nodes[parent].children[..] {
+            let grandparent = self.nodes[parent].parent;
+            merged = Some(Merged {
+                sibling,
+                sibling_edge: (self.nodes[sibling].length_to_parent, self.nodes[sibling].support),
+                parent_edge: (self.nodes[parent].length_to_parent, self.nodes[parent].support),
+                grandparent,
+                grand_slot: grandparent
+                    .and_then(|g| self.nodes[g].children.iter().position(|&c| c == parent))
+                    .unwrap_or(0),
+            });
+            self.suppress_unary(parent);
+        }
+        Ok(Detached { node, parent, slot, merged })
+    }
+
+    fn undo_detach(&mut self, detached: Detached) {
+        let Detached { node, parent, slot, merged } = detached;
+        if let Some(m) = merged { // Put the suppressed parent back between the sibling and the grandparent
+            (self.nodes[m.sibling].length_to_parent, self.nodes[m.sibling].support) = m.sibling_edge;
+            (self.nodes[parent].length_to_parent, self.nodes[parent].support) = m.parent_edge;
+            self.nodes[m.sibling].parent = Some(parent);
+            self.nodes[parent].children = vec![m.sibling];
+            self.nodes[parent].parent = m.grandparent;
+            match m.grandparent {
+                Some(g) => self.nodes[g].children[m.grand_slot] = parent,
+                None => self.root = parent,
+            }
+        }
+        self.nodes[parent].children.insert(slot, node);
+        self.nodes[node].parent = Some(parent);
+    }
+
+    fn graft_subtree(&mut self, subtree: NodeId, edge_child: NodeId, fraction: f64, reuse: Option<NodeId>) -> Result<Grafted, TreeError> {
+        let n = self.nodes.len();
+        if subtree >= n || subtree == self.root || self.nodes[subtree].parent.is_some() {
+            return Err(TreeError::InvalidNode(subtree)); // Only a detached subtree can be grafted
+        }
+        if edge_child >= n || self.ancestors(edge_child).last() != Some(&self.root) {
+            return Err(TreeError::InvalidNode(edge_child)); // The target edge must be part of the tree
+        }
+        if !(0.0..=1.0).contains(&fraction) {
+            return Err(TreeError::InvalidFraction(fraction));
+        }
+        self.lca_index = None;
+
+        let edge_length = self.nodes[edge_child].length_to_parent;
+        let parent = self.nodes[edge_child].parent;
+        let inserted_node = Node {
+            parent,
+            children: vec![edge_child, subtree],
+            length_to_parent: parent.and(edge_length.map(|l| l * (1.0 - fraction))),
+            support: self.nodes[edge_child].support, // Both halves of a split edge keep its support
+            ..Default::default()
+        };
+        let (inserted, reused) = match reuse {
+            Some(id) => (id, Some(std::mem::replace(&mut self.nodes[id], inserted_node))),
+            None => {
+                self.nodes.push(inserted_node);
+                (self.nodes.len() - 1, None)
+            }
+        };
+
+        match parent {
+            Some(p) => {
+                let slot = self.nodes[p].children.iter().position(|&c| c == edge_child).expect("child of its parent");
+                self.nodes[p].children[slot] = inserted;
+                self.nodes[edge_child].length_to_parent = edge_length.map(|l| l * fraction);
+            }
+            None => self.root = inserted, // Grafting above the root makes a new root
+        }
+        self.nodes[edge_child].parent = Some(inserted);
+        self.nodes[subtree].parent = Some(inserted);
+        Ok(Grafted { inserted, subtree, edge_child, edge_length, reused })
+    }
+
+    fn undo_graft(&mut self, grafted: Grafted) {
+        let Grafted { inserted, subtree, edge_child, edge_length, reused } = grafted;
+        let parent = self.nodes[inserted].parent;
+        match parent {
+            Some(p) => {
+                let slot = self.nodes[p].children.iter().position(|&c| c == inserted).expect("child of its parent");
+                self.nodes[p].children[slot] = edge_child;
+            }
+            None => self.root = edge_child,
+        }
+        self.nodes[edge_child].parent = parent;
+        self.nodes[edge_child].length_to_parent = edge_length;
+        self.nodes[subtree].parent = None;
+        match reused {
+            Some(node) => self.nodes[inserted] = node,
+            None => {
+                self.nodes.pop(); // The inserted node was the last one pushed
+            }
+        }
+    }
+
+    // Exchanges the positions of two nodes with different parents; each keeps its own edge data.
+    fn swap_subtrees(&mut self, a: NodeId, b: NodeId) {
+        self.lca_index = None;
+        let (pa, pb) = (self.nodes[a].parent.expect("a has a parent"), self.nodes[b].parent.expect("b has a parent"));
+        let slot_a = self.nodes[pa].children.iter().position(|&c| c == a).expect("child of its parent");
+        let slot_b = self.nodes[pb].children.iter().position(|&c| c == b).expect("child of its parent");
+        self.nodes[pa].children[slot_a] = b;
+        self.nodes[pb].children[slot_b] = a;
+        self.nodes[a].parent = Some(pb);
+        self.nodes[b].parent = Some(pa);
+    }
+}
@@ -0,0 +1,107 @@
+// This module provides constant-time lowest common ancestor queries. The tree is flattened into an Euler
+// tour (every node is written when entered and again after each child returns), so the LCA of a and b
+// is the shallowest node of the tour between the first visits of a and b. A sparse table of range minima
+// over the tour answers that in O(1) after O(n log n) preprocessing.
+use super::structure::{Tree, NodeId};
+
+// Precomputed LCA index of a tree. It describes the topology at the time it was built: the operations of
+// this crate drop it whenever they change the tree, and it is ignored once the node count or root no longer
+// match, but code that rewires `nodes` directly without changing either must call build_lca_index again
+// (or drop_lca_index).
+#[derive(Debug, Clone)]
+pub struct LcaIndex {
+    euler: Vec<NodeId>, // Euler tour of the tree, 2n - 1 entries
+    first: Vec<usize>, // first[node] = position of the first visit of node in the tour
+    depth: Vec<usize>, // depth[node] = number of edges from the root
+    root_distance: Vec<f64>, // root_distance[node] = sum of branch lengths from the root
+    sparse: Vec<Vec<u32>>, // sparse[k][i] = tour position of the shallowest node in euler[i .. i + 2^k]
+    root: NodeId, // Root of the tree the index was built from
+}
+
+impl LcaIndex {
+    pub fn new(tree: &Tree) -> Self {
+        let n = tree.nodes.len();
+        let mut euler = Vec::with_capacity(2 * n);
+        let mut first = vec![usize::MAX; n];
+        let mut depth = vec![0; n];
+        let mut root_distance = vec![0.0; n];
+
+        if n > 0 {
+            let mut stack = vec![(tree.root, 0)]; // (node, index of the next child to visit)
+            first[tree.root] = 0;
+            euler.push(tree.root);
+            while let Some(&mut (node, ref mut next)) = stack.last_mut() {
+                if let Some(&child) = tree.nodes[node].children.get(*next) {
+                    *next += 1;
+                    depth[child] = depth[node] + 1;
+                    root_distance[child] = root_distance[node] + tree.nodes[child].length_to_parent.unwrap_or(0.0);
+                    first[child] = euler.len();
+                    euler.push(child);
+                    stack.push((child, 0));
+                } else {
+                    stack.pop();
+                    if let Some(&(parent, _)) = stack.last() {
+                        euler.push(parent); // Back at the parent after finishing a child
+                    }
+                }
+            }
+        }
+
+        let mut sparse = vec![(0..euler.len() as u32).collect::<Vec<u32>>()];
+        let mut width = 1;
+        while 2 * width <= euler.len() {
+            let prev = sparse.last().expect("level 0 exists");
+            let level = (0..=euler.len() - 2 * width)
+                .map(|i| {
+                    let (a, b) = (prev[i], prev[i + width]);
+                    if depth[euler[a as usize]] <= depth[euler[b as usize]] { a } else { b }
+                })
+                .collect();
+            sparse.push(level);
+            width *= 2;
+        }
+
+        LcaIndex { euler, first, depth, root_distance, sparse, root: tree.root }
+    }
+
+    // Lowest common ancestor of two nodes, or None if either was not reachable from the root.
+    pub fn lca(&self, a: NodeId, b: NodeId) -> Option<NodeId> {
+        let (fa, fb) = (*self.first.get(a)?, *self.first.get(b)?);
+        if fa == usize::MAX || fb == usize::MAX {
+            return None;
+        }
+        let (l, r) = (fa.min(fb), fa.max(fb));
+        let k = (r - l + 1).ilog2() as usize;
+        let (x, y) = (self.sparse[k][l] as usize, self.sparse[k][r + 1 - (1 << k)] as usize);
+        Some(if self.depth[self.euler[x]] <= self.depth[self.euler[y]] { self.euler[x] } else { self.euler[y] })
+    }
+
+    pub fn depth(&self, node: NodeId) -> usize { // Number of edges between the node and the root
+        self.depth[node]
+    }
+
+    pub fn root_distance(&self, node: NodeId) -> f64 { // Sum of branch lengths between the node and the root
+        self.root_distance[node]
+    }
+
+    // Whether the index can still describe the tree: same node count and root as when it was built.
+    pub fn matches(&self, tree: &Tree) -> bool {
+        self.first.len() == tree.nodes.len() && self.root == tree.root
+    }
+}
+
+// Building and dropping the index stored on the tree
+impl Tree {
+    // Builds the LCA index; lca, topological_distance and patristic_distance use it until the tree changes.
+    pub fn build_lca_index(&mut self) {
+        self.lca_index = Some(LcaIndex::new(self));
+    }
+
+    pub fn drop_lca_index(&mut self) {
+        self.lca_index = None;
+    }
+
+    pub fn lca_index(&self) -> Option<&LcaIndex> { // The index, if built and still matching the tree
+        self.lca_index.as_ref().filter(|index| index.matches(self))
+    }
+}
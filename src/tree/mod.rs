@@ -1,3 +1,17 @@
 pub mod parser;
 pub mod structure;
-pub mod operations;
\ No newline at end of file
+pub mod operations;
+pub mod writer;
+pub mod network;
+pub mod taxa;
+pub mod splits;
+pub mod compare;
+pub mod lca;
+pub mod traversal;
+pub mod validate;
+pub mod edit;
+pub mod canonical;
+pub mod consensus;
+pub mod distance;
+pub mod construct;
+pub mod random;
\ No newline at end of file
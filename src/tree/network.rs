@@ -0,0 +1,124 @@
+// This module defines the data structures for representing a phylogenetic network.
+// Like Tree, the network is a vector of nodes addressed by index, but a node may have several parents
+// (a reticulation / hybrid node). Edge values live in a separate edge list, because a hybrid node has one
+// branch length and inheritance probability per parent edge.
+use super::structure::{NodeId, MetaValue};
+use std::collections::BTreeMap;
+
+pub type EdgeId = usize;
+
+// The kind of reticulation named by an extended Newick hybrid tag such as #H1, #LGT2 or #R3.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum HybridKind {
+    Hybridization, // #H
+    LateralGeneTransfer, // #LGT
+    Recombination, // #R
+    Other(String), // Any other prefix used by a tool
+}
+
+// Identifies a reticulation node: every occurrence of the same tag in the eNewick string is the same node.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct HybridTag {
+    pub kind: HybridKind,
+    pub index: usize, // The number following the kind, e.g. 1 in #H1
+}
+
+#[derive(Debug)]
+pub struct Network {
+    pub nodes: Vec<NetworkNode>, // Vector of nodes in the network
+    pub edges: Vec<NetworkEdge>, // Vector of directed parent -> child edges
+    pub root: NodeId, // Index of the root node in the nodes vector
+}
+
+#[derive(Debug)]
+pub struct NetworkNode {
+    pub parents: Vec<EdgeId>, // Incoming edges (empty for the root, two or more for a reticulation)
+    pub children: Vec<EdgeId>, // Outgoing edges
+    pub label: Option<String>, // Optional label for the node (without the hybrid tag)
+    pub hybrid: Option<HybridTag>, // Set for reticulation nodes
+    pub comments: Vec<String>, // [comments] from all occurrences of the node in the source
+    pub metadata: BTreeMap<String, MetaValue>, // Annotations parsed from those comments
+}
+
+#[derive(Debug, Clone)]
+pub struct NetworkEdge {
+    pub parent: NodeId,
+    pub child: NodeId,
+    pub length: Option<f64>, // Branch length
+    pub support: Option<f64>, // Support value written in the second eNewick field
+    pub gamma: Option<f64>, // Inheritance probability; only meaningful on edges into a reticulation
+}
+
+impl HybridTag {
+    // Parses the part of a label after '#', e.g. "H1" or "LGT21". Returns None unless a non-empty
+    // alphabetic kind is followed by the index.
+    pub fn parse(tag: &str) -> Option<Self> {
+        let split = tag.find(|c: char| c.is_ascii_digit())?; // Kind is everything before the first digit
+        let (kind, index) = tag.split_at(split);
+        if kind.is_empty() || !kind.chars().all(|c| c.is_ascii_alphabetic()) {
+            return None;
+        }
+        let index = index.parse().ok()?;
+
+        let kind = match kind {
+            "H" => HybridKind::Hybridization,
+            "LGT" => HybridKind::LateralGeneTransfer,
+            "R" => HybridKind::Recombination,
+            other => HybridKind::Other(other.to_string()),
+        };
+
+        Some(HybridTag { kind, index })
+    }
+}
+
+impl Network {
+    pub fn is_leaf(&self, node_id: NodeId) -> bool { // A node is a leaf if it has no children
+        self.nodes[node_id].children.is_empty()
+    }
+
+    pub fn is_hybrid(&self, node_id: NodeId) -> bool { // A reticulation node has more than one parent
+        self.nodes[node_id].parents.len() > 1
+    }
+
+    pub fn parents(&self, node_id: NodeId) -> Vec<NodeId> { // Returns the parent node IDs of the given node
+        self.nodes[node_id].parents.iter().map(|&e| self.edges[e].parent).collect()
+    }
+
+    pub fn children(&self, node_id: NodeId) -> Vec<NodeId> { // Returns the child node IDs of the given node
+        self.nodes[node_id].children.iter().map(|&e| self.edges[e].child).collect()
+    }
+
+    pub fn leaves(&self) -> Vec<NodeId> { // Returns the node IDs of all leaves
+        (0..self.nodes.len()).filter(|&id| self.is_leaf(id)).collect()
+    }
+
+    pub fn hybrid_nodes(&self) -> Vec<NodeId> { // Returns the node IDs of all reticulation nodes
+        (0..self.nodes.len()).filter(|&id| self.is_hybrid(id)).collect()
+    }
+
+    pub fn reticulation_count(&self) -> usize { // Number of extra parent edges, i.e. the network's reticulation number
+        self.nodes.iter().map(|n| n.parents.len().saturating_sub(1)).sum()
+    }
+
+    pub fn node_label(&self, node_id: NodeId) -> Option<&str> { // Returns the label of the node, if any
+        self.nodes[node_id].label.as_deref()
+    }
+
+    pub fn taxa(&self) -> Vec<&str> { // Returns the labels of the leaf nodes
+        self.leaves().into_iter().filter_map(|id| self.node_label(id)).collect()
+    }
+
+    pub fn edge_between(&self, parent: NodeId, child: NodeId) -> Option<&NetworkEdge> { // Returns the edge from parent to child, if present
+        self.nodes[child].parents.iter().map(|&e| &self.edges[e]).find(|e| e.parent == parent)
+    }
+
+    // Inheritance probability of the edge from parent to child. Edges into tree nodes inherit everything (1.0).
+    pub fn inheritance(&self, parent: NodeId, child: NodeId) -> Option<f64> {
+        let edge = self.edge_between(parent, child)?;
+        if self.is_hybrid(child) {
+            edge.gamma
+        } else {
+            Some(edge.gamma.unwrap_or(1.0))
+        }
+    }
+}
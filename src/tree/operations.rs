@@ -1,5 +1,49 @@
 // This file contains additional operations on the tree structure
-use super::structure::{Tree, NodeId};
+use super::structure::{Tree, NodeId, SupportScale};
+use std::fmt;
+
+
+// Errors returned by operations that change the shape of a tree.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TreeError {
+    InvalidNode(NodeId), // Node id out of range, or not usable for this operation (e.g. the root has no edge above it)
+    UnknownTaxon(String), // A requested label is not a leaf of the tree
+    EmptyTaxonSet, // An operation that needs at least one taxon got none
+    NotMonophyletic, // The outgroup does not form a clade in any rooting of the tree
+    InvalidFraction(f64), // A position along an edge outside [0, 1]
+    TooFewLeaves(usize), // The operation needs more leaves than the tree has
+    NoBranchLengths, // The operation needs positive branch lengths
+    DifferentTaxa, // Trees that must share one leaf set do not
+    IncompleteMatrix, // A distance matrix has missing entries
+    InvalidRate(f64), // A birth or death rate that a random tree model cannot use
+    NotEnoughNames(usize), // A list of taxon names shorter than the number of leaves requested
+    UnboundLeaf(NodeId), // A leaf without a taxon id where trees are compared by taxon (see Tree::bind_taxa)
+    NoTrees, // An operation on a collection of trees got an empty one
+    InvalidDistance(f64), // An infinite distance (or row sum) in a matrix used to build a tree
+}
+
+impl fmt::Display for TreeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TreeError::InvalidNode(id) => write!(f, "node {} cannot be used here", id),
+            TreeError::UnknownTaxon(name) => write!(f, "taxon '{}' is not a leaf of the tree", name),
+            TreeError::EmptyTaxonSet => write!(f, "no taxa given"),
+            TreeError::NotMonophyletic => write!(f, "outgroup is not monophyletic"),
+            TreeError::InvalidFraction(x) => write!(f, "edge position {} is outside [0, 1]", x),
+            TreeError::TooFewLeaves(n) => write!(f, "tree has too few leaves ({})", n),
+            TreeError::NoBranchLengths => write!(f, "tree has no positive branch lengths"),
+            TreeError::DifferentTaxa => write!(f, "trees do not have the same taxa"),
+            TreeError::IncompleteMatrix => write!(f, "distance matrix has missing entries"),
+            TreeError::InvalidRate(r) => write!(f, "invalid rate {}", r),
+            TreeError::NotEnoughNames(n) => write!(f, "only {} taxon names given", n),
+            TreeError::UnboundLeaf(id) => write!(f, "leaf {} has no taxon id (bind the tree to a TaxonSet first)", id),
+            TreeError::NoTrees => write!(f, "no trees given"),
+            TreeError::InvalidDistance(d) => write!(f, "invalid distance {}", d),
+        }
+    }
+}
+
+impl std::error::Error for TreeError {}
 
 
 
@@ -8,6 +52,9 @@ use super::structure::{Tree, NodeId};
 impl Tree { // Calculate the topological distance (number of edges) between two nodes
     pub fn topological_distance(&self, a: NodeId, b: NodeId) -> Option<usize> { // Find the lowest common ancestor (LCA) of the two nodes
         let lca = self.lca(a, b)?; // Calculate the depth of each node and the LCA
+        if let Some(index) = self.lca_index() { // Depths are precomputed in the LCA index
+            return Some(index.depth(a) + index.depth(b) - 2 * index.depth(lca));
+        }
         let da = self.depth(a); // Depth of node a
         let db = self.depth(b); // Depth of node b
         let dl = self.depth(lca); // Depth of the LCA
@@ -17,6 +64,9 @@ impl Tree { // Calculate the topological distance (number of edges) between two
 
     pub fn patristic_distance(&self, mut a: NodeId, mut b: NodeId) -> Option<f64> { // Calculate the patristic distance (sum of branch lengths) between two nodes
         let lca = self.lca(a, b)?; // Initialise the distance to zero
+        if let Some(index) = self.lca_index() { // Root distances are precomputed in the LCA index
+            return Some(index.root_distance(a) + index.root_distance(b) - 2.0 * index.root_distance(lca));
+        }
         let mut dist = 0.0; // Traverse from node a to the LCA, adding the branch lengths
 
         while a != lca { // Add the length to the parent node, if it exists
@@ -45,6 +95,7 @@ impl Tree { // Calculate the topological distance (number of edges) between two
                 let mut sum = 0; // Sum the counts of all child nodes
                 for &child in &self.nodes[node].children { // Add the count of each child to the sum
                     sum += counts[child]; // The count for the current node is the total number of descendant leaves
+                }
                 counts[node] = sum; // This method allows us to quickly determine how many leaves are in the subtree rooted at any given node
             }
         }
@@ -54,9 +105,352 @@ impl Tree { // Calculate the topological distance (number of edges) between two
 }
 
 
+// Branch support stored in internal node labels
+impl Tree {
+    // Moves numeric internal labels such as ")95:0.1" or ")0.87:0.02" into the support field of the node,
+    // i.e. onto the edge above that node, and clears the label. Non-numeric labels (clade names), leaf labels
+    // and the root label are left untouched. The scale chosen is recorded in support_scale so the writer
+    // puts the values back as they were read. Returns the number of labels converted.
+    pub fn labels_to_support(&mut self, scale: SupportScale) -> usize {
+        let root = self.root;
+        let numeric: Vec<(NodeId, f64)> = self.nodes.iter().enumerate() // Internal, non-root nodes with a numeric label
+            .filter(|&(id, node)| id != root && !node.children.is_empty())
+            .filter_map(|(id, node)| node.label.as_deref()?.trim().parse::<f64>().ok().map(|v| (id, v)))
+            .collect();
+
+        let percent = match scale { // Decide the scale once for the whole tree
+            SupportScale::Percent => true,
+            SupportScale::Proportion => false,
+            SupportScale::Auto => numeric.iter().any(|&(_, v)| v > 1.0),
+        };
+
+        for &(id, value) in &numeric {
+            self.nodes[id].support = Some(if percent { value / 100.0 } else { value });
+            self.nodes[id].label = None; // The label was a support value, not a name
+        }
+        if !numeric.is_empty() {
+            self.support_scale = Some(if percent { SupportScale::Percent } else { SupportScale::Proportion });
+        }
+
+        numeric.len()
+    }
+}
+
+
+// Rerooting. Branch lengths and support values describe the edge above a node, so whenever the
+// direction of an edge flips they are moved to the node that is now below it. Convert numeric support
+// labels with labels_to_support before rerooting, as labels stay on their node.
+impl Tree {
+    // Makes the given node the root by reversing the edges on its path to the current root. If the old
+    // root is left with a single child it is removed and its two edges are merged into one. Node ids are
+    // compacted afterwards, so ids held from before the call are no longer valid. A leaf cannot become the
+    // root without losing its taxon, so rerooting at a leaf roots on its pendant edge, next to the leaf.
+    pub fn reroot_at_node(&mut self, new_root: NodeId) -> Result<(), TreeError> {
+        if new_root >= self.nodes.len() {
+            return Err(TreeError::InvalidNode(new_root));
+        }
+        let old_root = self.root;
+        if new_root == old_root {
+            return Ok(());
+        }
+        if self.is_leaf(new_root) {
+            return self.reroot_on_edge(new_root, 0.0);
+        }
+
+        self.lca_index = None; // The index describes the old rooting
+        let path = self.ancestors(new_root); // new_root, ..., old_root
+        let edges: Vec<(Option<f64>, Option<f64>)> = path.iter() // Edge data above each node of the path
+            .map(|&id| (self.nodes[id].length_to_parent, self.nodes[id].support))
+            .collect();
+
+        for i in 0..path.len() - 1 { // Flip the edge between path[i] and its parent path[i + 1]
+            let (child, parent) = (path[i], path[i + 1]);
+            self.nodes[parent].children.retain(|&c| c != child);
+            self.nodes[child].children.push(parent);
+            self.nodes[parent].parent = Some(child);
+            self.nodes[parent].length_to_parent = edges[i].0; // The edge keeps its length and support,
+            self.nodes[parent].support = edges[i].1; // but is now stored on its other end
+        }
+
+        self.nodes[new_root].parent = None;
+        self.nodes[new_root].length_to_parent = None;
+        self.nodes[new_root].support = None;
+        self.root = new_root;
+
+        if self.nodes[old_root].children.len() == 1 { // The old root is now a degree-2 node
+            self.suppress_unary(old_root);
+            self.compact();
+        }
+        Ok(())
+    }
+
+    // Roots the tree on the edge above `child`, at `fraction` of the edge length measured from the child
+    // (0.0 = at the child, 1.0 = at its parent). A new root node is inserted there; both halves of the edge
+    // keep its support.
+    pub fn reroot_on_edge(&mut self, child: NodeId, fraction: f64) -> Result<(), TreeError> {
+        if child >= self.nodes.len() || child == self.root {
+            return Err(TreeError::InvalidNode(child));
+        }
+        if !(0.0..=1.0).contains(&fraction) {
+            return Err(TreeError::InvalidFraction(fraction));
+        }
+
+        let parent = self.nodes[child].parent.ok_or(TreeError::InvalidNode(child))?;
+        let length = self.nodes[child].length_to_parent;
+        let support = self.nodes[child].support;
+        let id = self.nodes.len();
+        self.lca_index = None;
+
+        self.nodes.push(super::structure::Node { // New node splitting the edge in two
+            parent: Some(parent),
+            children: vec![child],
+            length_to_parent: length.map(|l| l * (1.0 - fraction)),
+            support,
+            ..Default::default()
+        });
+        let slot = self.nodes[parent].children.iter().position(|&c| c == child).expect("child of its parent");
+        self.nodes[parent].children[slot] = id; // Keep the child order of the parent
+        self.nodes[child].parent = Some(id);
+        self.nodes[child].length_to_parent = length.map(|l| l * fraction);
+
+        self.reroot_at_node(id)?;
+        self.compact(); // reroot_at_node only compacts when it removed the old root
+        Ok(())
+    }
 
+    // Roots the tree on the edge separating the outgroup taxa from all others, halfway along the edge.
+    // The outgroup must form a clade on one side of some edge of the unrooted tree.
+    pub fn root_with_outgroup(&mut self, outgroup: &[&str]) -> Result<(), TreeError> {
+        if outgroup.is_empty() {
+            return Err(TreeError::EmptyTaxonSet);
+        }
+        let leaves = self.leaves();
+        let mut in_outgroup = vec![0; self.nodes.len()]; // 1 for outgroup leaves
+        for name in outgroup {
+            let leaf = leaves.iter().copied().find(|&l| self.node_label(l) == Some(*name))
+                .ok_or_else(|| TreeError::UnknownTaxon(name.to_string()))?;
+            in_outgroup[leaf] = 1;
+        }
+
+        // Count outgroup leaves below every node, alongside all leaves.
+        let leaf_counts = self.compute_subtree_leaf_counts();
+        let mut out_counts = in_outgroup;
+        for node in self.postorder() {
+            for &child in &self.nodes[node].children {
+                out_counts[node] += out_counts[child];
+            }
+        }
+        let (k, total) = (out_counts[self.root], leaf_counts[self.root]);
+
+        // The edge above a node whose clade is exactly the outgroup, or exactly the ingroup, separates them.
+        let edge = (0..self.nodes.len())
+            .filter(|&id| id != self.root)
+            .find(|&id| (out_counts[id] == k && leaf_counts[id] == k) || (out_counts[id] == 0 && leaf_counts[id] == total - k))
+            .ok_or(TreeError::NotMonophyletic)?;
 
+        self.reroot_on_edge(edge, 0.5)
+    }
 
+    // Roots the tree at the midpoint of its longest leaf-to-leaf path (missing lengths count as 0).
+    pub fn reroot_at_midpoint(&mut self) -> Result<(), TreeError> {
+        let leaves = self.leaves();
+        if leaves.len() < 2 {
+            return Ok(()); // Nothing to balance
+        }
 
+        // The farthest leaf from any leaf is one end of a longest path; the farthest from it is the other.
+        let farthest = |from: NodeId| -> (NodeId, f64) {
+            leaves.iter()
+                .map(|&l| (l, self.patristic_distance(from, l).unwrap_or(0.0)))
+                .fold((from, 0.0), |best, cur| if cur.1 > best.1 { cur } else { best })
+        };
+        let (a, _) = farthest(leaves[0]);
+        let (b, diameter) = farthest(a);
+        if a == b || diameter == 0.0 {
+            return Ok(());
+        }
 
+        // Walk from the end that is farther from the LCA until the midpoint is passed.
+        let lca = self.lca(a, b).ok_or(TreeError::InvalidNode(a))?;
+        let half = diameter / 2.0;
+        let up_a = self.patristic_distance(a, lca).unwrap_or(0.0);
+        let (start, target) = if up_a >= half { (a, half) } else { (b, diameter - half) };
 
+        let mut node = start;
+        let mut travelled = 0.0;
+        loop {
+            let length = self.nodes[node].length_to_parent.unwrap_or(0.0);
+            if travelled + length >= target && length > 0.0 {
+                let fraction = (target - travelled) / length; // Position of the midpoint along this edge
+                return self.reroot_on_edge(node, fraction.clamp(0.0, 1.0));
+            }
+            travelled += length;
+            node = self.nodes[node].parent.ok_or(TreeError::InvalidNode(node))?;
+        }
+    }
+
+    // Removes a node with exactly one child, joining its parent edge and child edge into one edge.
+    // The removed node is left detached; call compact() to drop it.
+    pub(crate) fn suppress_unary(&mut self, node: NodeId) {
+        self.lca_index = None;
+        let child = self.nodes[node].children[0];
+        let parent = self.nodes[node].parent;
+        let length = match (self.nodes[node].length_to_parent, self.nodes[child].length_to_parent) {
+            (Some(a), Some(b)) => Some(a + b),
+            (a, b) => a.or(b),
+        };
+
+        self.nodes[child].parent = parent;
+        self.nodes[child].length_to_parent = if parent.is_some() { length } else { None };
+        self.nodes[child].support = self.nodes[child].support.or(self.nodes[node].support);
+        match parent {
+            Some(p) => {
+                let slot = self.nodes[p].children.iter().position(|&c| c == node).expect("child of its parent");
+                self.nodes[p].children[slot] = child;
+            }
+            None => self.root = child, // Removing a unary root makes its child the root
+        }
+        self.nodes[node].children.clear();
+        self.nodes[node].parent = None;
+    }
+
+    // Drops nodes that are no longer reachable from the root and renumbers the rest, keeping their
+    // relative order. Node ids held from before the call are invalidated.
+    pub fn compact(&mut self) {
+        let mut keep = vec![false; self.nodes.len()];
+        for id in self.preorder() {
+            keep[id] = true;
+        }
+        if keep.iter().all(|&k| k) {
+            return;
+        }
+        self.lca_index = None; // Node ids change
+
+        let mut new_id = vec![usize::MAX; self.nodes.len()];
+        let mut next = 0;
+        for (id, &k) in keep.iter().enumerate() {
+            if k {
+                new_id[id] = next;
+                next += 1;
+            }
+        }
+
+        let old = std::mem::take(&mut self.nodes);
+        for (id, mut node) in old.into_iter().enumerate() {
+            if !keep[id] {
+                continue;
+            }
+            node.parent = node.parent.map(|p| new_id[p]);
+            for c in node.children.iter_mut() {
+                *c = new_id[*c];
+            }
+            self.nodes.push(node);
+        }
+        self.root = new_id[self.root];
+    }
+}
+
+
+// Induced subtrees. The result keeps only the chosen leaves and their ancestors; nodes left with a
+// single child are suppressed and their two edges merged (lengths add, support is kept from the lower
+// edge when present). The original tree is left untouched.
+impl Tree {
+    // Returns the subtree induced by the leaves with the given labels. Fails on a label that is not a leaf.
+    pub fn restrict_to(&self, taxa: &[&str]) -> Result<Tree, TreeError> {
+        let leaves = self.leaves();
+        let mut keep = Vec::with_capacity(taxa.len());
+        for name in taxa {
+            let leaf = leaves.iter().copied().find(|&l| self.node_label(l) == Some(*name))
+                .ok_or_else(|| TreeError::UnknownTaxon(name.to_string()))?;
+            keep.push(leaf);
+        }
+        self.restrict_to_leaves(&keep)
+    }
+
+    // Returns the tree without the leaves with the given labels. Labels absent from the tree are ignored,
+    // so one list of unwanted taxa can be applied to every gene tree of a set.
+    pub fn prune(&self, taxa: &[&str]) -> Result<Tree, TreeError> {
+        let keep: Vec<NodeId> = self.leaves().into_iter()
+            .filter(|&l| self.node_label(l).is_none_or(|name| !taxa.contains(&name)))
+            .collect();
+        self.restrict_to_leaves(&keep)
+    }
+
+    // Returns the subtree induced by the given leaf nodes, e.g. the four leaves of a quartet.
+    pub fn restrict_to_leaves(&self, leaves: &[NodeId]) -> Result<Tree, TreeError> {
+        let mut keep = vec![false; self.nodes.len()];
+        for &leaf in leaves {
+            if leaf >= self.nodes.len() || !self.is_leaf(leaf) {
+                return Err(TreeError::InvalidNode(leaf));
+            }
+            for id in self.ancestors(leaf) {
+                if keep[id] {
+                    break; // The rest of the path is already marked
+                }
+                keep[id] = true;
+            }
+        }
+        if leaves.is_empty() {
+            return Err(TreeError::EmptyTaxonSet);
+        }
+
+        let mut tree = self.clone();
+        let order = self.postorder();
+        for &id in &order {
+            tree.nodes[id].children.retain(|&c| keep[c]);
+        }
+        for &id in &order { // Children are handled before parents, so merged edges accumulate correctly
+            if keep[id] && tree.nodes[id].children.len() == 1 {
+                tree.suppress_unary(id);
+            }
+        }
+        tree.compact();
+        Ok(tree)
+    }
+}
+
+
+// Collapsing poorly supported or very short internal edges into polytomies. The children of a contracted
+// node are attached to its parent in its place, and the length of the removed edge is added to theirs so
+// patristic distances between leaves are kept. Edges without the relevant value are never collapsed.
+impl Tree {
+    // Contracts every internal edge whose support is below `threshold` (a proportion, see labels_to_support).
+    // Returns the number of edges removed. Node ids are compacted afterwards.
+    pub fn collapse_below_support(&mut self, threshold: f64) -> usize {
+        self.collapse_edges(|tree, id| tree.nodes[id].support.is_some_and(|s| s < threshold))
+    }
+
+    // Contracts every internal edge shorter than `threshold`, e.g. 1e-6 for the near-zero edges left by ML
+    // programs that only output binary trees. Returns the number of edges removed.
+    pub fn collapse_below_length(&mut self, threshold: f64) -> usize {
+        self.collapse_edges(|tree, id| tree.nodes[id].length_to_parent.is_some_and(|l| l < threshold))
+    }
+
+    // Contracts the edge above every internal, non-root node for which `collapse` returns true. The
+    // predicate sees the tree as it was before any edge was removed.
+    pub fn collapse_edges<F: Fn(&Tree, NodeId) -> bool>(&mut self, collapse: F) -> usize {
+        let targets: Vec<NodeId> = self.postorder().into_iter() // Children first, so lengths accumulate downwards
+            .filter(|&id| id != self.root && !self.is_leaf(id) && collapse(self, id))
+            .collect();
+
+        for &node in &targets {
+            let parent = self.nodes[node].parent.expect("non-root node has a parent");
+            let length = self.nodes[node].length_to_parent;
+            let children = std::mem::take(&mut self.nodes[node].children);
+            for &child in &children {
+                self.nodes[child].parent = Some(parent);
+                if let (Some(a), Some(b)) = (self.nodes[child].length_to_parent, length) {
+                    self.nodes[child].length_to_parent = Some(a + b);
+                }
+            }
+            let slot = self.nodes[parent].children.iter().position(|&c| c == node).expect("child of its parent");
+            self.nodes[parent].children.splice(slot..=slot, children);
+            self.nodes[node].parent = None;
+        }
+
+        if !targets.is_empty() {
+            self.compact();
+        }
+        targets.len()
+    }
+}
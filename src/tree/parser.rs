@@ -1,150 +1,1137 @@
 // This module is responsible for parsing tree data from various formats (e.g., Newick, Nexus).
 
-use super::structure::{Tree, Node, NodeId}; // Importing the Tree, Node, and NodeId types from the structure module.
+use super::structure::{Tree, Node, NodeId, MetaValue, SupportScale}; // Importing the Tree, Node, and NodeId types from the structure module.
+use super::taxa::TaxonSet;
+use super::network::{Network, NetworkNode, NetworkEdge, EdgeId, HybridTag};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
 
 // enums for supported formats and error handling
 #[derive(Debug)]
-pub enum ParseError {
+pub enum ParseErrorKind {
     UnexpectedEnd,
     UnexpectedToken(char),
     UnbalancedParentheses,
     InvalidBranchLength,
+    InvalidNetwork(String), // Extended Newick that does not describe a valid network (bad hybrid tag, cycle, ...)
+    InvalidNexus(String), // NEXUS structure problem (missing header, malformed TRANSLATE or TREE command)
+    InvalidTree(String), // Parsed, but rejected by Tree::validate in strict mode (e.g. duplicate leaf labels)
+    Io(io::Error), // Error raised by the underlying reader while streaming trees from a file
 }
 
+// Location of a character in the parsed input. Line and column are 1-based, the column counts characters.
+// For trees read through NewickReader the position is relative to the whole file, not the single tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub offset: usize, // Byte offset from the start of the input
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Position {
+    pub fn start() -> Self {
+        Position { offset: 0, line: 1, column: 1 }
+    }
+
+    // Moves the position past one character of input.
+    fn advance(&mut self, c: char) {
+        self.offset += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+    }
+
+    // Moves the position past a chunk of raw bytes, counting only the first byte of each UTF-8 character.
+    fn advance_bytes(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.offset += 1;
+            if b == b'\n' {
+                self.line += 1;
+                self.column = 1;
+            } else if b & 0xC0 != 0x80 { // Skip UTF-8 continuation bytes
+                self.column += 1;
+            }
+        }
+    }
+}
+
+// A parse failure together with where it happened and the text that caused it.
+#[derive(Debug)]
+pub struct ParseError {
+    pub kind: ParseErrorKind, // What went wrong
+    pub position: Position, // Where the offending text starts
+    pub tree_index: Option<usize>, // 0-based index of the tree within a multi-tree file, if read through a reader
+    pub snippet: String, // The offending text (token, label, branch length, ...), possibly empty
+}
+
+impl ParseError {
+    fn new(kind: ParseErrorKind, position: Position, snippet: impl Into<String>) -> Self {
+        ParseError { kind, position, tree_index: None, snippet: snippet.into() }
+    }
+
+    // Shifts a position computed within a single tree's text to the position of that text in the file.
+    fn relocate(mut self, base: Position, tree_index: usize) -> Self {
+        if self.position.line == 1 { // Still on the line the tree started on
+            self.position.column += base.column - 1;
+        }
+        self.position.line += base.line - 1;
+        self.position.offset += base.offset;
+        self.tree_index = Some(tree_index);
+        self
+    }
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseErrorKind::UnexpectedEnd => write!(f, "unexpected end of input"),
+            ParseErrorKind::UnexpectedToken(c) => write!(f, "unexpected character '{}'", c),
+            ParseErrorKind::UnbalancedParentheses => write!(f, "unbalanced parentheses"),
+            ParseErrorKind::InvalidBranchLength => write!(f, "invalid branch length"),
+            ParseErrorKind::InvalidNetwork(msg) => write!(f, "invalid network: {}", msg),
+            ParseErrorKind::InvalidNexus(msg) => write!(f, "invalid NEXUS: {}", msg),
+            ParseErrorKind::InvalidTree(msg) => write!(f, "invalid tree: {}", msg),
+            ParseErrorKind::Io(e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    // e.g. "tree 12, line 12, column 31 (byte 402): invalid branch length near `0.1x`"
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(index) = self.tree_index {
+            write!(f, "tree {}, ", index + 1)?; // Humans count trees from 1
+        }
+        write!(f, "line {}, column {} (byte {}): {}", self.position.line, self.position.column, self.position.offset, self.kind)?;
+        if !self.snippet.is_empty() {
+            write!(f, " near `{}`", self.snippet)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match &self.kind {
+            ParseErrorKind::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ParseError {
+    fn from(err: io::Error) -> Self {
+        ParseError::new(ParseErrorKind::Io(err), Position::start(), "")
+    }
+}
+
+
+// Splits a BufRead source into ';'-terminated statements without loading the whole source. A ';' inside a
+// quoted label or a [comment] does not end a statement. Shared by the Newick and NEXUS readers.
+struct StatementScanner<R: BufRead> {
+    reader: R, // Underlying buffered source
+    record: Vec<u8>, // Raw bytes of the statement currently being read, reused between statements
+    position: Position, // Position in the source of the next unread byte
+}
 
+impl<R: BufRead> StatementScanner<R> {
+    fn new(reader: R) -> Self {
+        StatementScanner {
+            reader,
+            record: Vec::new(),
+            position: Position::start(),
+        }
+    }
+
+    // Reads the raw text of the next statement (up to and including its ';'), skipping records that
+    // contain only whitespace such as the trailing newline after the last tree. Returns None at EOF,
+    // otherwise the text and the position in the source where it starts.
+    fn next_statement(&mut self) -> io::Result<Option<(String, Position)>> {
+        loop {
+            self.record.clear(); // Reuse the allocation from the previous statement
+            let start = self.position;
+
+            if self.read_until_terminator()? == 0 { // Nothing left in the source
+                return Ok(None);
+            }
+            self.position.advance_bytes(&self.record);
+
+            if self.record.iter().all(|b| b.is_ascii_whitespace() || *b == b';') { // Blank record, keep going
+                continue;
+            }
+
+            let text = String::from_utf8(std::mem::take(&mut self.record)) // Tree files must be valid UTF-8
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            return Ok(Some((text, start)));
+        }
+    }
+
+    // Appends bytes to the record up to the next ';' that is not inside a quoted label or a [comment].
+    // Returns the number of bytes consumed from the source.
+    fn read_until_terminator(&mut self) -> io::Result<usize> {
+        let mut quote: Option<u8> = None; // Quote character of the label we are inside, if any
+        let mut comment_depth = 0; // Nesting depth of [comments]
+        let mut prev = b'('; // Previous byte, used to tell an opening quote from an apostrophe inside a label
+        let mut consumed = 0;
+
+        loop {
+            let buf = self.reader.fill_buf()?;
+            if buf.is_empty() { // EOF, return whatever was collected
+                return Ok(consumed);
+            }
+
+            let mut end = None; // Position of the terminating ';' within this buffer
+            for (i, &b) in buf.iter().enumerate() {
+                match quote {
+                    Some(q) if b == q => quote = None, // A doubled quote simply reopens on the next byte
+                    Some(_) => {}
+                    None if comment_depth > 0 => match b {
+                        b'[' => comment_depth += 1,
+                        b']' => comment_depth -= 1,
+                        _ => {}
+                    },
+                    None => match b {
+                        b'\'' | b'"' if matches!(prev, b'(' | b',' | b')' | b']' | b';' | b'=') || prev.is_ascii_whitespace() || prev == b => quote = Some(b),
+                        b'[' => comment_depth = 1,
+                        b';' => {
+                            end = Some(i);
+                            break;
+                        }
+                        _ => {}
+                    },
+                }
+                prev = b;
+            }
+
+            let used = end.map_or(buf.len(), |i| i + 1); // Include the ';' itself
+            self.record.extend_from_slice(&buf[..used]);
+            self.reader.consume(used);
+            consumed += used;
+
+            if end.is_some() {
+                return Ok(consumed);
+            }
+        }
+    }
+
+    // Turns a read error into a ParseError located at the current position.
+    fn io_error(&self, err: io::Error, tree_index: usize) -> ParseError {
+        let mut err = ParseError::from(err);
+        err.position = self.position;
+        err.tree_index = Some(tree_index);
+        err
+    }
+}
+
+
+// Streaming reader over any BufRead source (file, stdin, in-memory bytes) holding many Newick trees.
+// Trees may be one per line or concatenated; each ';' terminates a tree. Only the text of the tree
+// currently being parsed is held in memory, so arbitrarily large gene tree files can be processed.
+pub struct NewickReader<R: BufRead> {
+    taxa: Option<TaxonSet>, // Shared namespace every tree is bound to, if requested
+    scanner: StatementScanner<R>, // Splits the source into ';'-terminated trees
+    options: ParseOptions, // Applied to every tree read
+    finished: bool, // Set once the source is exhausted or an I/O error has been reported
+    tree_index: usize, // Index of the next tree to be yielded
+}
+
+impl NewickReader<BufReader<File>> {
+    // Opens a Newick file on disk and wraps it in a buffered streaming reader.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::open(path)?; // Propagate missing files and permission errors to the caller
+        Ok(NewickReader::new(BufReader::new(file)))
+    }
+}
+
+impl<R: BufRead> NewickReader<R> {
+    pub fn new(reader: R) -> Self {
+        NewickReader {
+            taxa: None,
+            scanner: StatementScanner::new(reader),
+            options: ParseOptions::default(),
+            finished: false,
+            tree_index: 0,
+        }
+    }
+
+    // Sets the options applied to every tree read.
+    pub fn with_options(mut self, options: ParseOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    // Binds every tree read to the given taxon set (which grows as new names are met), so that taxon
+    // ids agree across the whole file. Use taxa() or into_taxa() to get the set back.
+    pub fn with_taxa(mut self, taxa: TaxonSet) -> Self {
+        self.taxa = Some(taxa);
+        self
+    }
+
+    pub fn taxa(&self) -> Option<&TaxonSet> { // The shared taxon set, if one was given
+        self.taxa.as_ref()
+    }
+
+    pub fn into_taxa(self) -> Option<TaxonSet> { // Consumes the reader and returns the shared taxon set
+        self.taxa
+    }
+}
+
+impl<R: BufRead> Iterator for NewickReader<R> {
+    type Item = Result<Tree, ParseError>;
+
+    // Yields the next parsed tree. A malformed tree produces an Err for that tree only and the
+    // reader moves on to the following one; an I/O error is reported once and ends the stream.
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        match self.scanner.next_statement() {
+            Ok(Some((text, start))) => { // Parse the isolated tree text, reporting errors relative to the file
+                let index = self.tree_index;
+                self.tree_index += 1;
+                let mut result = parse_nwk_with(&text, &self.options).map_err(|e| e.relocate(start, index));
+                if let (Ok(tree), Some(taxa)) = (&mut result, &mut self.taxa) {
+                    tree.bind_taxa(taxa);
+                }
+                Some(result)
+            }
+            Ok(None) => { // End of input
+                self.finished = true;
+                None
+            }
+            Err(e) => { // Unrecoverable read error
+                self.finished = true;
+                Some(Err(self.scanner.io_error(e, self.tree_index)))
+            }
+        }
+    }
+}
+
+
+// Streaming reader for the TREES blocks of a NEXUS file (as written by MrBayes, BEAST, PAUP*, ...).
+// Other blocks are skipped. If a block has a TRANSLATE table, leaf labels are replaced by the taxon names
+// they stand for. Trees are yielded one at a time, exactly like NewickReader.
+pub struct NexusReader<R: BufRead> {
+    taxa: Option<TaxonSet>, // Shared namespace every tree is bound to, if requested
+    scanner: StatementScanner<R>, // Splits the source into ';'-terminated NEXUS commands
+    options: ParseOptions, // Applied to every tree read
+    finished: bool, // Set once the source is exhausted or an error ended the stream
+    header_checked: bool, // Whether the leading #NEXUS has been seen
+    in_trees_block: bool, // Whether we are between BEGIN TREES and END
+    translate: HashMap<String, String>, // TRANSLATE table of the current TREES block
+    tree_index: usize, // Index of the next tree to be yielded
+    tree_name: Option<String>, // Name given to the most recently yielded tree
+}
+
+impl NexusReader<BufReader<File>> {
+    // Opens a NEXUS file on disk and wraps it in a buffered streaming reader.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::open(path)?;
+        Ok(NexusReader::new(BufReader::new(file)))
+    }
+}
+
+impl<R: BufRead> NexusReader<R> {
+    pub fn new(reader: R) -> Self {
+        NexusReader {
+            taxa: None,
+            scanner: StatementScanner::new(reader),
+            options: ParseOptions::default(),
+            finished: false,
+            header_checked: false,
+            in_trees_block: false,
+            translate: HashMap::new(),
+            tree_index: 0,
+            tree_name: None,
+        }
+    }
+
+    // Sets the options applied to every tree read.
+    pub fn with_options(mut self, options: ParseOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    // Binds every tree read to the given taxon set (which grows as new names are met), so that taxon
+    // ids agree across the whole file. Use taxa() or into_taxa() to get the set back.
+    pub fn with_taxa(mut self, taxa: TaxonSet) -> Self {
+        self.taxa = Some(taxa);
+        self
+    }
+
+    pub fn taxa(&self) -> Option<&TaxonSet> { // The shared taxon set, if one was given
+        self.taxa.as_ref()
+    }
+
+    pub fn into_taxa(self) -> Option<TaxonSet> { // Consumes the reader and returns the shared taxon set
+        self.taxa
+    }
+
+    // Name of the tree most recently returned by the iterator (e.g. "STATE_1000" or "gene1").
+    pub fn tree_name(&self) -> Option<&str> {
+        self.tree_name.as_deref()
+    }
+
+    // Translate table of the TREES block being read (token -> taxon name).
+    pub fn translate_table(&self) -> &HashMap<String, String> {
+        &self.translate
+    }
+
+    // Handles one NEXUS command. Returns Some for TREE commands, None for everything else.
+    fn handle_statement(&mut self, text: &str, start: Position) -> Option<Result<Tree, ParseError>> {
+        let mut body = skip_comments_and_whitespace(text); // Command text without leading comments
+
+        if !self.header_checked { // The file must start with #NEXUS, which has no ';' of its own
+            self.header_checked = true;
+            if !body.get(..6).is_some_and(|h| h.eq_ignore_ascii_case("#nexus")) {
+                self.finished = true; // Not a NEXUS file, nothing sensible can follow
+                return Some(Err(ParseError::new(ParseErrorKind::InvalidNexus("missing #NEXUS header".to_string()), start, text.trim().chars().take(20).collect::<String>())));
+            }
+            body = skip_comments_and_whitespace(&body[6..]);
+        }
+
+        let keyword_end = body.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(body.len());
+        let keyword = body[..keyword_end].to_ascii_lowercase();
+        let rest = &body[keyword_end..];
+
+        match keyword.as_str() {
+            "begin" => { // BEGIN <block>;
+                let block = rest.trim().trim_end_matches(';').trim();
+                self.in_trees_block = block.eq_ignore_ascii_case("trees");
+                self.translate.clear(); // Each TREES block has its own table
+            }
+            "end" | "endblock" => self.in_trees_block = false,
+            "translate" if self.in_trees_block => {
+                if let Err(msg) = self.read_translate(rest) {
+                    return Some(Err(ParseError::new(ParseErrorKind::InvalidNexus(msg), start, "TRANSLATE")));
+                }
+            }
+            "tree" | "utree" if self.in_trees_block => return Some(self.read_tree(text, rest, start)),
+            _ => {} // Commands of other blocks, or ones we have no use for
+        }
+        None
+    }
+
+    // Parses "1 Homo_sapiens, 2 'Pan troglodytes', ...;" into the translate table.
+    fn read_translate(&mut self, args: &str) -> Result<(), String> {
+        for entry in split_outside_quotes(args.trim().trim_end_matches(';'), ',') { // Entries are comma separated
+            let tokens = nexus_tokens(entry);
+            match tokens.as_slice() {
+                [] => {} // Tolerate a trailing comma
+                [key, name] => {
+                    self.translate.insert(key.clone(), name.clone());
+                }
+                _ => return Err(format!("malformed TRANSLATE entry '{}'", entry.trim())),
+            }
+        }
+        Ok(())
+    }
+
+    // Parses "[*] name = [comments] (newick);" and applies the translate table to the leaves.
+    // `statement` is the whole command and `args` the part after the TREE keyword.
+    fn read_tree(&mut self, statement: &str, args: &str, start: Position) -> Result<Tree, ParseError> {
+        let index = self.tree_index;
+        self.tree_index += 1;
+
+        let Some(eq) = find_outside_quotes(args, '=') else {
+            let mut err = ParseError::new(ParseErrorKind::InvalidNexus("TREE command without '='".to_string()), start, "TREE");
+            err.tree_index = Some(index);
+            return Err(err);
+        };
+
+        self.tree_name = nexus_tokens(args[..eq].trim().trim_start_matches('*')).into_iter().next();
+
+        let newick_start = statement.len() - args.len() + eq + 1; // Byte offset of the Newick text within the statement
+        let mut base = start; // Position of the Newick text within the file
+        for c in statement[..newick_start].chars() {
+            base.advance(c);
+        }
+
+        let mut tree = parse_nwk_with(&statement[newick_start..], &self.options).map_err(|e| e.relocate(base, index))?;
+
+        if !self.translate.is_empty() {
+            for node in tree.nodes.iter_mut().filter(|n| n.children.is_empty()) { // Only leaves are translated
+                if let Some(name) = node.label.as_ref().and_then(|l| self.translate.get(l)) {
+                    node.label = Some(name.clone());
+                }
+            }
+        }
+
+        if let Some(taxa) = &mut self.taxa { // Bind after translation so ids refer to real taxon names
+            tree.bind_taxa(taxa);
+        }
+
+        Ok(tree)
+    }
+}
+
+impl<R: BufRead> Iterator for NexusReader<R> {
+    type Item = Result<Tree, ParseError>;
+
+    // Yields the next tree of any TREES block. Like NewickReader, a malformed tree only fails itself.
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.finished {
+            match self.scanner.next_statement() {
+                Ok(Some((text, start))) => {
+                    if let Some(result) = self.handle_statement(&text, start) {
+                        return Some(result);
+                    }
+                }
+                Ok(None) => self.finished = true, // End of input
+                Err(e) => { // Unrecoverable read error
+                    self.finished = true;
+                    return Some(Err(self.scanner.io_error(e, self.tree_index)));
+                }
+            }
+        }
+        None
+    }
+}
 
+// Opens a tree file in either NEXUS or Newick format, deciding from its first bytes, and returns an
+// iterator over its trees.
+pub fn read_tree_file<P: AsRef<Path>>(path: P) -> io::Result<Box<dyn Iterator<Item = Result<Tree, ParseError>>>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let head = reader.fill_buf()?; // Peek without consuming
+    let text = String::from_utf8_lossy(&head[..head.len().min(64)]);
+
+    if text.trim_start().get(..6).is_some_and(|h| h.eq_ignore_ascii_case("#nexus")) {
+        Ok(Box::new(NexusReader::new(reader)))
+    } else {
+        Ok(Box::new(NewickReader::new(reader)))
+    }
+}
+
+// Returns the text after any leading whitespace and [comments].
+fn skip_comments_and_whitespace(text: &str) -> &str {
+    let mut rest = text.trim_start();
+    while rest.starts_with('[') {
+        let mut depth = 0;
+        let end = rest.char_indices().find_map(|(i, c)| {
+            match c {
+                '[' => depth += 1,
+                ']' => depth -= 1,
+                _ => {}
+            }
+            (depth == 0).then_some(i + 1)
+        });
+        match end {
+            Some(end) => rest = rest[end..].trim_start(),
+            None => return "", // Unterminated comment swallows the rest
+        }
+    }
+    rest
+}
+
+// Byte index of the first separator character that is not inside quotes or [comments].
+fn find_outside_quotes(text: &str, separator: char) -> Option<usize> {
+    let mut quote: Option<char> = None; // Quote character we are inside, if any
+    let mut depth = 0; // Nesting depth of [comments]
+
+    for (i, c) in text.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None, // A doubled quote simply reopens on the next character
+            (Some(_), _) => {}
+            (None, '[') => depth += 1,
+            (None, ']') if depth > 0 => depth -= 1,
+            (None, '\'' | '"') if depth == 0 => quote = Some(c),
+            (None, c) if c == separator && depth == 0 => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+// Splits text on a separator character that is not inside quotes or [comments].
+fn split_outside_quotes(mut text: &str, separator: char) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    while let Some(i) = find_outside_quotes(text, separator) {
+        pieces.push(&text[..i]);
+        text = &text[i + separator.len_utf8()..];
+    }
+    pieces.push(text);
+    pieces
+}
+
+// Splits a NEXUS phrase into whitespace-separated words, unquoting quoted words and dropping [comments].
+fn nexus_tokens(text: &str) -> Vec<String> {
+    let mut cursor = Cursor::new(text);
+    let mut tokens = Vec::new();
+
+    while let Some(c) = cursor.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                cursor.next();
+            }
+            '[' => {
+                if read_comment(&mut cursor).is_err() { // Unterminated comment ends the phrase
+                    break;
+                }
+            }
+            '\'' | '"' => match read_label(&mut cursor) { // Quoted word, may contain spaces
+                Ok(word) => tokens.push(word),
+                Err(_) => break, // Unterminated quote ends the phrase
+            },
+            _ => { // Unquoted word runs until whitespace or a comment
+                let mut word = String::new();
+                while let Some(c) = cursor.peek().filter(|&c| !c.is_whitespace() && c != '[') {
+                    word.push(c);
+                    cursor.next();
+                }
+                tokens.push(word);
+            }
+        }
+    }
+    tokens
+}
+
+
+// Character cursor over the input that keeps track of the current position for error reporting.
+struct Cursor<'a> {
+    input: &'a str,
+    position: Position,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(input: &'a str) -> Self {
+        Cursor { input, position: Position::start() }
+    }
+
+    fn rest(&self) -> &'a str { // Unread part of the input
+        &self.input[self.position.offset..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position.advance(c);
+        Some(c)
+    }
+
+    // Error at the given position, quoting a short excerpt of the input from there as the offending text.
+    fn error_at(&self, kind: ParseErrorKind, position: Position) -> ParseError {
+        let excerpt: String = self.input[position.offset..].chars().take(20).collect();
+        ParseError::new(kind, position, excerpt)
+    }
+}
+
+// Options applied to every tree parsed through parse_nwk_with or a reader configured with_options.
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    pub support: Option<SupportScale>, // Read numeric internal labels as branch support on this scale (see Tree::labels_to_support)
+    pub strict: bool, // Reject trees that fail Tree::validate (duplicate or missing leaf labels, bad lengths)
+}
+
+// Parses a Newick string and applies the post-processing requested in the options.
+pub fn parse_nwk_with(input: &str, options: &ParseOptions) -> Result<Tree, ParseError> {
+    let raw = parse_raw(input, false)?;
+    let positions = raw.positions; // Kept to point strict-mode errors at the offending node
+    let mut tree = Tree { nodes: raw.nodes, root: raw.root, lca_index: None, support_scale: None };
+    if options.strict {
+        let issues = tree.validate();
+        if let Some(first) = issues.first() {
+            let node = first.node().unwrap_or(tree.root);
+            let message = issues.iter().map(|issue| issue.to_string()).collect::<Vec<_>>().join("; ");
+            let snippet = tree.node_label(node).unwrap_or_default().to_string();
+            return Err(ParseError::new(ParseErrorKind::InvalidTree(message), positions[node], snippet));
+        }
+    }
+    if let Some(scale) = options.support {
+        tree.labels_to_support(scale);
+    }
+    Ok(tree)
+}
 
 // The parse_newick function takes a Newick formatted string and returns a Tree structure.
+// Labels may be unquoted or wrapped in single/double quotes (a doubled quote inside a quoted label is an
+// escaped quote character), and bracketed [comments] are kept on the node they follow.
 pub fn parse_nwk(input: &str) -> Result<Tree, ParseError> { // 
+    let raw = parse_raw(input, false)?; // Plain Newick: a single branch length after ':'
+    Ok(Tree { nodes: raw.nodes, root: raw.root, lca_index: None, support_scale: None })
+}
+
+// Parses an extended Newick (eNewick) string into a Network. Reticulation nodes are written as labels
+// carrying a hybrid tag, e.g. "(A,(B)#H1:1::0.3)" and "(#H1:2::0.7,C)": every occurrence of #H1 is the same
+// node, reached through one edge per occurrence. At most one occurrence may list the node's children.
+// Edge fields are ':length:support:gamma'; a missing gamma on one parent edge of a reticulation is
+// filled in so that the node's inheritance probabilities sum to 1.
+pub fn parse_enwk(input: &str) -> Result<Network, ParseError> {
+    let raw = parse_raw(input, true)?;
+    let invalid = |i: NodeId, msg: String| { // Network error pointing at the i-th raw node
+        let label = raw.nodes[i].label.clone().unwrap_or_default();
+        ParseError::new(ParseErrorKind::InvalidNetwork(msg), raw.positions[i], label)
+    };
+
+    let mut nodes: Vec<NetworkNode> = Vec::new();
+    let mut map = vec![0; raw.nodes.len()]; // Raw node index -> network node index
+    let mut by_tag: HashMap<HybridTag, NodeId> = HashMap::new(); // Network node of each hybrid tag seen so far
+    let mut has_definition = vec![false; raw.nodes.len()]; // Per network node: an occurrence with children was seen
+    let mut quoted = vec![false; raw.nodes.len()]; // Quoted labels are names only, never hybrid tags
+    for &i in &raw.quoted {
+        quoted[i] = true;
+    }
+
+    for (i, node) in raw.nodes.iter().enumerate() {
+        let (label, tag) = match node.label.as_deref().filter(|_| !quoted[i]).and_then(|l| l.find('#').map(|p| (l, p))) {
+            Some((l, p)) => { // Label with a hybrid tag
+                let tag = HybridTag::parse(&l[p + 1..]).ok_or_else(|| invalid(i, format!("malformed hybrid tag '{}'", &l[p..])))?;
+                let name = Some(l[..p].to_string()).filter(|n| !n.is_empty());
+                (name, Some(tag))
+            }
+            None => (node.label.clone(), None),
+        };
+
+        let existing = tag.as_ref().and_then(|t| by_tag.get(t).copied());
+        let id = match existing {
+            Some(id) => { // Another occurrence of a reticulation already created
+                if nodes[id].label.is_none() {
+                    nodes[id].label = label;
+                }
+                nodes[id].comments.extend(node.comments.iter().cloned());
+                nodes[id].metadata.extend(node.metadata.iter().map(|(k, v)| (k.clone(), v.clone())));
+                id
+            }
+            None => {
+                let id = nodes.len();
+                if let Some(t) = &tag {
+                    by_tag.insert(t.clone(), id);
+                }
+                nodes.push(NetworkNode {
+                    parents: Vec::new(),
+                    children: Vec::new(),
+                    label,
+                    hybrid: tag,
+                    comments: node.comments.clone(),
+                    metadata: node.metadata.clone(),
+                });
+                id
+            }
+        };
+
+        if !node.children.is_empty() { // This occurrence lists the node's children
+            if has_definition[id] {
+                return Err(invalid(i, "hybrid node has children in more than one occurrence".to_string()));
+            }
+            has_definition[id] = true;
+        }
+        map[i] = id;
+    }
+
+    let mut edges: Vec<NetworkEdge> = Vec::new();
+    for (i, node) in raw.nodes.iter().enumerate() { // One edge for every parent link of every occurrence
+        let Some(parent) = node.parent else { continue };
+        let fields = raw.edges[i].unwrap_or_default();
+        let (parent, child) = (map[parent], map[i]);
+        let edge_id = edges.len();
+
+        edges.push(NetworkEdge { parent, child, length: fields.length, support: fields.support, gamma: fields.gamma });
+        nodes[parent].children.push(edge_id);
+        nodes[child].parents.push(edge_id);
+    }
+
+    let root = map[raw.root];
+    if !nodes[root].parents.is_empty() {
+        return Err(invalid(raw.root, "the root cannot be a reticulation".to_string()));
+    }
+
+    for node in &nodes { // Complete the inheritance probabilities of reticulations with one missing gamma
+        let missing: Vec<EdgeId> = node.parents.iter().copied().filter(|&e| edges[e].gamma.is_none()).collect();
+        if node.parents.len() > 1 && missing.len() == 1 {
+            let known: f64 = node.parents.iter().filter_map(|&e| edges[e].gamma).sum();
+            edges[missing[0]].gamma = Some((1.0 - known).max(0.0));
+        }
+    }
+
+    // A hybrid occurrence inside its own subtree would make the graph cyclic; check that every node can be
+    // reached in topological order from the root (Kahn's algorithm).
+    let mut remaining: Vec<usize> = nodes.iter().map(|n| n.parents.len()).collect();
+    let mut queue = vec![root];
+    let mut visited = 0;
+    while let Some(id) = queue.pop() {
+        visited += 1;
+        for &e in &nodes[id].children {
+            let child = edges[e].child;
+            remaining[child] -= 1;
+            if remaining[child] == 0 {
+                queue.push(child);
+            }
+        }
+    }
+    if visited != nodes.len() { // Report a childless occurrence of a reticulation left unvisited, i.e. the reference inside its own subtree
+        let stuck = |i: &usize| remaining[map[*i]] > 0 && nodes[map[*i]].hybrid.is_some();
+        let i = (0..raw.nodes.len()).filter(stuck).find(|&i| raw.nodes[i].children.is_empty())
+            .or_else(|| (0..raw.nodes.len()).find(stuck))
+            .unwrap_or(raw.root);
+        return Err(invalid(i, "hybrid nodes form a cycle".to_string()));
+    }
+
+    Ok(Network { nodes, edges, root })
+}
+
+// Parses a Newick string and binds its leaves to the shared taxon set (see Tree::bind_taxa).
+pub fn parse_nwk_in(input: &str, taxa: &mut TaxonSet) -> Result<Tree, ParseError> {
+    let mut tree = parse_nwk(input)?;
+    tree.bind_taxa(taxa);
+    Ok(tree)
+}
+
+// Values written after a node's ':'. Plain Newick only has the branch length; extended Newick
+// (eNewick) allows ':length:support:gamma' where any of the fields may be left empty.
+#[derive(Debug, Default, Clone, Copy)]
+struct EdgeFields {
+    length: Option<f64>,
+    support: Option<f64>,
+    gamma: Option<f64>, // Inheritance probability of a hybrid edge
+}
+
+// Tree-shaped result of the shared Newick/eNewick parser, before eNewick hybrid nodes are merged.
+struct RawTree {
+    nodes: Vec<Node>,
+    root: NodeId,
+    edges: Vec<Option<EdgeFields>>, // Per node: the fields written after its ':', if any
+    positions: Vec<Position>, // Per node: where it starts in the input, for later diagnostics
+    quoted: Vec<NodeId>, // Nodes whose label was quoted, so a '#' in it is part of the name
+}
+
+// Parses one Newick tree into nodes. With `extended` set, edges may carry the eNewick support and
+// inheritance-probability fields; otherwise exactly one branch length is accepted after ':'.
+fn parse_raw(input: &str, extended: bool) -> Result<RawTree, ParseError> {
     let mut nodes: Vec<Node> = Vec::new(); // Vector to hold the nodes of the tree as they are parsed.
-    let mut stack: Vec<NodeId> = Vec::new(); // Stack to keep track of the current parent nodes as we parse through the tree structure.
+    let mut edges: Vec<Option<EdgeFields>> = Vec::new(); // Edge fields for each node, kept in step with `nodes`
+    let mut positions: Vec<Position> = Vec::new(); // Start position of each node, kept in step with `nodes`
+    let mut stack: Vec<(NodeId, Position)> = Vec::new(); // Stack of open clades and where their '(' was, to report unclosed ones.
 
-    let mut chars = input.chars().peekable(); // Create a peekable iterator over the characters of the input string
+    let mut cursor = Cursor::new(input); // Cursor over the characters of the input string
     let mut root: Option<NodeId> = None; // Variable to hold the index of the root node once it is identified.
+    let mut last: Option<NodeId> = None; // The most recently completed node, which receives any following label, branch length or comment.
+    let mut pending_comments: Vec<String> = Vec::new(); // Comments seen before the node they belong to exists (e.g. a leading [&R]).
+    let mut quoted: Vec<NodeId> = Vec::new(); // Nodes with a quoted label
 
-    while let Some(c) = chars.next() { // Loop through each character in the input string
+    while let Some(c) = cursor.peek() { // Loop through each character in the input string
+        let start = cursor.position; // Where the current token begins
         match c { // Match the character to determine how to parse it
             '(' => { // When we encounter an opening parenthesis, we create a new node and push it onto the stack.
+                cursor.next(); // consume '('
                 let id = nodes.len(); // The ID of the new node is the current length of the nodes vector, which will be its index.
+                let parent = stack.last().map(|&(p, _)| p); // The parent is the clade currently open, or None for the root
+
+                if parent.is_none() && root.is_some() { // A second top-level clade: the tree has already been closed
+                    return Err(ParseError::new(ParseErrorKind::UnexpectedToken('('), start, "("));
+                }
 
                 nodes.push(Node { // Create a new node with the appropriate parent and push it onto the nodes vector.
-                    parent: stack.last().copied(), // The parent of the new node is the last element on the stack (the current parent node), or None if the stack is empty.
+                    parent, // The parent of the new node is the last element on the stack (the current parent node), or None if the stack is empty.
                     children: Vec::new(), // Initialise the children vector for the new node.
-                    label: None, // The label for this node is None, as it is an internal node created by the '(' character.
+                    label: None, // The label for this node is None for now; an internal label may follow the matching ')'.
+                    taxon: None, // Taxon ids are only assigned when parsing against a TaxonSet
                     length_to_parent: None, // The branch length to the parent is None for now, as it may be specified later in the Newick format after the label. We will update this if we encounter a colon followed by a branch length value.
+                    support: None, // Only set when numeric labels are read as support (see ParseOptions)
+                    comments: std::mem::take(&mut pending_comments), // Comments written before the node opened belong to it
+                    metadata: BTreeMap::new(), // Filled from the comments once parsing is done
                 });
+                edges.push(None);
+                positions.push(start);
 
-                if let Some(&parent_id) = stack.last() { // If there is a parent node on the stack, we add the new node as a child of that parent.
+                if let Some(parent_id) = parent { // If there is a parent node on the stack, we add the new node as a child of that parent.
                     nodes[parent_id].children.push(id); // Add the new node's ID to the children of the parent node.
                 }
 
-                stack.push(id); // Push the new node's ID onto the stack, making it the current parent for any subsequent nodes until we encounter a closing parenthesis.
+                stack.push((id, start)); // Push the new node's ID onto the stack, making it the current parent for any subsequent nodes until we encounter a closing parenthesis.
+                last = None; // Nothing has been completed inside the new clade yet
 
                 if root.is_none() { // If the root node has not been set yet, we set it to the ID of the new node, as this is the first node we encounter and will be the root of the tree.
                     root = Some(id); // Set the root node ID to the current node ID if it hasn't been set yet.
                 } // Note: The root node is the first node created when we encounter the first '(' character, and it will be the parent of all other nodes in the tree.
             }
 
-            ')' => {
-                let node_id = stack.pop().ok_or(ParseError::UnbalancedParentheses)?;
+            ')' => { // A closing parenthesis completes the internal node on top of the stack.
+                cursor.next(); // consume ')'
+                if last.is_none() && let Some(&(parent_id, _)) = stack.last() { // '()' or ',)' closes over an unlabeled leaf
+                    push_leaf(&mut nodes, &mut edges, &mut positions, parent_id, None, std::mem::take(&mut pending_comments), start);
+                }
+                let (node_id, _) = stack.pop().ok_or_else(|| ParseError::new(ParseErrorKind::UnbalancedParentheses, start, ")"))?;
+                last = Some(node_id); // Its label, length and comments may follow
+            }
 
-                // Check if there is a label for this internal node
-                if let Some(&next) = chars.peek() {
-                    if next != ':' && next != ',' && next != ')' && next != ';' {
-                        let mut label = String::new();
+            ',' => { // A comma indicates a sibling node, so the next label starts a new node.
+                cursor.next();
+                let &(parent_id, _) = stack.last().ok_or_else(|| ParseError::new(ParseErrorKind::UnexpectedToken(','), start, ","))?; // Siblings only exist inside a clade
+                if last.is_none() { // '(,' or ',,' separates an unlabeled leaf
+                    push_leaf(&mut nodes, &mut edges, &mut positions, parent_id, None, std::mem::take(&mut pending_comments), start);
+                }
+                last = None;
+            }
 
-                        while let Some(&c) = chars.peek() {
-                            if c == ':' || c == ',' || c == ')' || c == ';' {
-                                break;
-                            }
-                            label.push(chars.next().unwrap());
-                        }
+            ';' => break, // A semicolon indicates the end of the tree definition, so we break out of the loop.
 
-                        nodes[node_id].label = Some(label);
-                    }
+            '[' => { // Bracketed comment, attached to the node it follows.
+                let comment = read_comment(&mut cursor)?;
+                match last {
+                    Some(id) => nodes[id].comments.push(comment),
+                    None => pending_comments.push(comment), // Attach to the next node created
                 }
+            }
 
-                // Check for branch length
-                if let Some(&':') = chars.peek() {
-                    chars.next(); // consume ':'
+            ':' => { // Branch length (and eNewick edge fields) of the node just completed.
+                let id = match (last, stack.last()) { // A length must follow a node, and only one per node
+                    (Some(id), _) if edges[id].is_none() => id,
+                    (None, Some(&(parent_id, _))) => { // '(:0.2' or ',:0.2' is the length of an unlabeled leaf
+                        let id = push_leaf(&mut nodes, &mut edges, &mut positions, parent_id, None, std::mem::take(&mut pending_comments), start);
+                        last = Some(id);
+                        id
+                    }
+                    _ => return Err(cursor.error_at(ParseErrorKind::UnexpectedToken(':'), start)),
+                };
+                let fields = read_edge_fields(&mut cursor, extended)?;
+                nodes[id].length_to_parent = fields.length;
+                edges[id] = Some(fields);
+            }
 
-                    let mut number = String::new();
+            c if c.is_whitespace() => { // If the character is whitespace, we ignore it and continue to the next character.
+                cursor.next();
+            }
 
-                    while let Some(&next) = chars.peek() {
-                        if next == ',' || next == ')' || next == ';' {
-                            break;
+            _ => { // For any other character, we assume it is the start of a label for a node.
+                let is_quoted = matches!(c, '\'' | '"');
+                let label = read_label(&mut cursor)?; // Read a quoted or unquoted label
+                let unexpected = || ParseError::new(ParseErrorKind::UnexpectedToken(c), start, &cursor.input[start.offset..cursor.position.offset]);
+
+                match last {
+                    Some(id) if !nodes[id].children.is_empty() && nodes[id].label.is_none() && edges[id].is_none() => {
+                        nodes[id].label = Some(label); // Label of the internal node closed by the preceding ')'
+                        if is_quoted {
+                            quoted.push(id);
                         }
-                        number.push(chars.next().unwrap());
                     }
-
-                    let length = number
-                        .parse::<f64>()
-                        .map_err(|_| ParseError::InvalidBranchLength)?;
-
-                    nodes[node_id].length_to_parent = Some(length);
+                    Some(_) => return Err(unexpected()), // Two labels in a row for the same node
+                    None => { // Start of a new leaf
+                        let &(parent_id, _) = stack.last().ok_or_else(unexpected)?; // A leaf must be nested inside a clade
+                        let id = push_leaf(&mut nodes, &mut edges, &mut positions, parent_id, Some(label), std::mem::take(&mut pending_comments), start);
+                        if is_quoted {
+                            quoted.push(id);
+                        }
+                        last = Some(id);
+                    }
                 }
             }
-            ',' => continue, // A comma indicates a sibling node, so we simply continue to the next character without doing anything special.
+        }
+    }
 
-            ';' => break, // A semicolon indicates the end of the tree definition, so we break out of the loop.
+    if let Some(&(_, open)) = stack.last() { // After processing all characters, an open clade means unbalanced parentheses; report the innermost unclosed '('.
+        return Err(cursor.error_at(ParseErrorKind::UnbalancedParentheses, open));
+    }
 
-            c if c.is_whitespace() => continue, // If the character is whitespace, we ignore it and continue to the next character.
+    let root_id = root.ok_or_else(|| ParseError::new(ParseErrorKind::UnexpectedEnd, cursor.position, ""))?; // If we never set the root node (i.e., we never encountered an opening parenthesis), the input ended without defining a tree.
+    nodes[root_id].comments.append(&mut pending_comments); // Trailing comments with no following node describe the whole tree
 
-            _ => { // For any other character, we assume it is the start of a label for a node. We read the label until we encounter a comma, closing parenthesis, or semicolon.
-                let mut label = String::new(); // Create a new string to hold the label of the node.
-                label.push(c); // Push the first character of the label onto the string.
+    for node in nodes.iter_mut() { // Turn NHX and BEAST annotation comments into typed metadata
+        for comment in &node.comments {
+            parse_annotations(comment, &mut node.metadata);
+        }
+    }
 
-                while let Some(&next) = chars.peek() { // Peek at the next character to see if it is part of the label. If it is a comma, closing parenthesis, or semicolon, we stop reading the label.
-                    if next == ',' || next == ')' || next == ';'|| next == ':'{ // If the next character is a comma, closing parenthesis, semicolon, or colon (indicating a branch length), we break out of the loop, as these characters indicate the end of the label.
-                        break; // Break out of the loop if we encounter a character that indicates the end of the label.
-                    } // If the next character is not a comma, closing parenthesis, semicolon, or colon, we read it as part of the label and continue.
-                    label.push(chars.next().unwrap()); // Push the next character onto the label string and consume it from the iterator.
-                } // After reading the label, we create a new node with the label and add it to the nodes vector. We also update the parent-child relationships based on the current state of the stack.
-                
-                let mut length_to_parent = None; // Variable to hold the branch length to the parent node, if specified in the Newick format.
+    Ok(RawTree { nodes, root: root_id, edges, positions, quoted }) // If we successfully parsed the input string, we return the nodes, the index of the root node and the per-node edge fields.
+}
 
-                if let Some(&':') = chars.peek() { // If the next character is a colon, it indicates that there is a branch length specified for this node. We read the branch length and store it in the length_to_parent variable.
-                    chars.next(); // consume ':'
+// Appends a leaf under `parent_id`, keeping the per-node edge fields and positions in step, and returns its id.
+// Unlabeled leaves (as in '(,B)' or '(:0.2,B)') are created with `label` None.
+fn push_leaf(nodes: &mut Vec<Node>, edges: &mut Vec<Option<EdgeFields>>, positions: &mut Vec<Position>, parent_id: NodeId, label: Option<String>, comments: Vec<String>, start: Position) -> NodeId {
+    let id = nodes.len(); // The ID of the new node is the current length of the nodes vector, which will be its index.
+    nodes.push(Node {
+        parent: Some(parent_id),
+        children: Vec::new(),
+        label,
+        taxon: None,
+        length_to_parent: None, // Filled in if a ':' follows
+        support: None,
+        comments,
+        metadata: BTreeMap::new(),
+    });
+    edges.push(None);
+    positions.push(start);
+    nodes[parent_id].children.push(id); // Establish the parent-child relationship in the tree structure.
+    id
+}
 
-                    let mut number = String::new(); // Create a new string to hold the characters of the branch length as we read them from the input.
+// Parses an annotation comment (without brackets) into key/value metadata. Two dialects are recognised:
+// NHX "&&NHX:S=human:B=95" (':'-separated) and BEAST "&posterior=0.98,height=1.2,rate_range={0.1,0.5}"
+// (','-separated, braces for lists). Other comments are ignored. Values are typed as Int, Float or Str.
+fn parse_annotations(comment: &str, metadata: &mut BTreeMap<String, MetaValue>) {
+    let (body, separator) = if let Some(nhx) = comment.strip_prefix("&&NHX") {
+        (nhx.trim_start_matches(':'), ':')
+    } else if let Some(beast) = comment.strip_prefix('&') {
+        (beast, ',')
+    } else {
+        return; // Plain comment, nothing to extract
+    };
 
-                    while let Some(&next) = chars.peek() { // Peek at the next character to see if it is part of the branch length. If it is a comma, closing parenthesis, or semicolon, we stop reading the branch length.
-                        if next == ',' || next == ')' || next == ';' { // If the next character is a comma, closing parenthesis, or semicolon, we break out of the loop, as these characters indicate the end of the branch length.
-                            break; // Break out of the loop if we encounter a character that indicates the end of the branch length.
-                        } // If the next character is not a comma, closing parenthesis, or semicolon, we read it as part of the branch length and continue.
-                        number.push(chars.next().unwrap()); // Push the next character onto the number string and consume it from the iterator.
-                    } // After reading the branch length as a string, we attempt to parse it as a floating-point number and store it in the length_to_parent variable. If parsing fails, we return an error indicating that the branch length is invalid.
+    for pair in split_outside_braces(body, separator) {
+        if let Some((key, value)) = pair.split_once('=') {
+            let key = key.trim();
+            if !key.is_empty() {
+                metadata.insert(key.to_string(), parse_meta_value(value.trim()));
+            }
+        }
+    }
+}
 
-                    length_to_parent = Some( // Attempt to parse the branch length string as a floating-point number. If parsing fails, we return an error indicating that the branch length is invalid.
-                        number.parse::<f64>() // Parse the number string as a floating-point number. If parsing fails, it will return an error, which we map to our custom ParseError::InvalidBranchLength error type.
-                            .map_err(|_| ParseError::InvalidBranchLength)? // If parsing is successful, store it in the length_to_parent variable.
-                    );
-                }
+// Types a single annotation value: {a,b,...} lists, integers, floats, and strings (quotes removed).
+fn parse_meta_value(value: &str) -> MetaValue {
+    if let Some(inner) = value.strip_prefix('{').and_then(|v| v.strip_suffix('}')) {
+        return MetaValue::List(split_outside_braces(inner, ',').into_iter().map(|v| parse_meta_value(v.trim())).collect());
+    }
+    if let Ok(i) = value.parse::<i64>() {
+        return MetaValue::Int(i);
+    }
+    if let Ok(x) = value.parse::<f64>() {
+        return MetaValue::Float(x);
+    }
+    let unquoted = value
+        .strip_prefix('"').and_then(|v| v.strip_suffix('"'))
+        .or_else(|| value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')))
+        .unwrap_or(value);
+    MetaValue::Str(unquoted.to_string())
+}
 
-                let id = nodes.len(); // The ID of the new node is the current length of the nodes vector, which will be its index.
+// Splits on a separator that is not inside {braces} or quotes.
+fn split_outside_braces(text: &str, separator: char) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut depth = 0; // Nesting depth of braces
+    let mut quote: Option<char> = None;
+    let mut start = 0;
 
-                nodes.push(Node { // Create a new node with the appropriate parent and label, and push it onto the nodes vector.
-                    parent: stack.last().copied(), // The parent of the new node is the last element on the stack (the current parent node), or None if the stack is empty.
-                    children: Vec::new(), // Initialise the children vector for the new node, as it may have children if it is an internal node.
-                    label: Some(label), // Set the label of the new node to the string we just read from the input.
-                    length_to_parent, // <-- match struct
-                });
+    for (i, c) in text.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '{') => depth += 1,
+            (None, '}') => depth -= 1,
+            (None, c) if c == separator && depth == 0 => {
+                pieces.push(&text[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    pieces.push(&text[start..]);
+    pieces
+}
+
+// Characters that end an unquoted label or branch length.
+fn is_delimiter(c: char) -> bool {
+    matches!(c, '(' | ')' | ',' | ':' | ';' | '[')
+}
+
+// Reads a node label. Quoted labels ('...' or "...") may contain any character, with a doubled quote
+// standing for a literal quote. Unquoted labels run until the next delimiter; surrounding whitespace is dropped.
+fn read_label(cursor: &mut Cursor) -> Result<String, ParseError> {
+    let start = cursor.position;
+    let mut label = String::new();
+
+    if let Some(quote) = cursor.peek()
+        && (quote == '\'' || quote == '"') {
+        cursor.next(); // consume the opening quote
 
-                if let Some(&parent_id) = stack.last() { // If there is a parent node on the stack, we add the new node as a child of that parent.
-                    nodes[parent_id].children.push(id); // Add the new node's ID to the children of the parent node, establishing the parent-child relationship in the tree structure.
-                } else { // If there is no parent node on the stack, it means this node is not properly nested within the tree structure, and we return an error.
-                    return Err(ParseError::UnexpectedToken(c)); // Return an error if we encounter a label that is not properly nested within the tree structure (i.e., it is not a child of any node).
+        loop {
+            match cursor.next() {
+                Some(c) if c == quote => {
+                    if cursor.peek() == Some(quote) { // Doubled quote is an escaped literal quote
+                        label.push(quote);
+                        cursor.next();
+                    } else { // Closing quote
+                        return Ok(label);
+                    }
                 }
+                Some(c) => label.push(c),
+                None => return Err(cursor.error_at(ParseErrorKind::UnexpectedEnd, start)), // Unterminated quoted label
             }
         }
     }
 
-    if !stack.is_empty() { // After processing all characters, if the stack is not empty, it means we have unbalanced parentheses (i.e., there are more opening parentheses than closing parentheses), and we return an error.
-        return Err(ParseError::UnbalancedParentheses); // Return an error if we have unbalanced parentheses, which indicates that the tree structure is not properly defined in the input string.
+    while let Some(c) = cursor.peek() { // Unquoted label: read until a delimiter
+        if is_delimiter(c) {
+            break;
+        }
+        label.push(c);
+        cursor.next();
+    }
+
+    Ok(label.trim_end().to_string()) // Whitespace before the delimiter is not part of the label
+}
+
+// Reads a [comment] starting at its opening bracket. Nested brackets are kept as part of the text.
+fn read_comment(cursor: &mut Cursor) -> Result<String, ParseError> {
+    let start = cursor.position;
+    cursor.next(); // consume '['
+    let mut comment = String::new();
+    let mut depth = 1; // Number of currently open brackets
+
+    while let Some(c) = cursor.next() {
+        match c {
+            '[' => depth += 1,
+            ']' => {
+                depth -= 1;
+                if depth == 0 { // Matching close bracket
+                    return Ok(comment);
+                }
+            }
+            _ => {}
+        }
+        comment.push(c);
     }
 
-    let root_id = root.ok_or(ParseError::UnexpectedEnd)?; // If we never set the root node (i.e., we never encountered an opening parenthesis), we return an error indicating that the input ended unexpectedly without defining a valid tree structure.
+    Err(cursor.error_at(ParseErrorKind::UnexpectedEnd, start)) // Unterminated comment
+}
+
+// Reads the fields after a ':' (starting at the ':'). Plain Newick requires one branch length; extended
+// Newick accepts up to three colon-separated fields (length, support, gamma), each of which may be empty.
+fn read_edge_fields(cursor: &mut Cursor, extended: bool) -> Result<EdgeFields, ParseError> {
+    let mut values = [None; 3]; // length, support, gamma
+    let max_fields = if extended { 3 } else { 1 };
 
-    Ok(Tree { nodes, root: root_id }) // If we successfully parsed the input string and constructed the tree structure, we return the Tree object containing the vector of nodes and the index of the root node.
+    for (i, value) in values.iter_mut().enumerate().take(max_fields) {
+        if i > 0 && cursor.peek() != Some(':') { // No further fields
+            break;
+        }
+        cursor.next(); // consume ':'
+        *value = read_number(cursor, extended)?;
+    }
+
+    let [length, support, gamma] = values;
+    Ok(EdgeFields { length, support, gamma })
 }
 
+// Reads a number field, skipping leading whitespace. An empty field gives None when `allow_empty` is set.
+fn read_number(cursor: &mut Cursor, allow_empty: bool) -> Result<Option<f64>, ParseError> {
+    while cursor.peek().is_some_and(|c| c.is_whitespace()) { // Allow "A: 0.1"
+        cursor.next();
+    }
+
+    let start = cursor.position;
+    let mut number = String::new();
+    while let Some(c) = cursor.peek() {
+        if is_delimiter(c) || c.is_whitespace() {
+            break;
+        }
+        number.push(c);
+        cursor.next();
+    }
+
+    if number.is_empty() && allow_empty {
+        return Ok(None);
+    }
+
+    number.parse::<f64>().map(Some).map_err(|_| ParseError::new(ParseErrorKind::InvalidBranchLength, start, number))
+}
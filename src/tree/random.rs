@@ -0,0 +1,274 @@
+// This module generates random and reference trees for testing and simulation:
+// - Yule (pure birth) and birth–death trees with branch lengths in units of time,
+// - uniformly distributed rooted binary topologies,
+// - balanced trees and caterpillars.
+// A TreeGenerator owns its own small PRNG (SplitMix64), so a seed gives the same trees on every platform
+// and crate version without external dependencies. Leaf names come from a TaxonNaming scheme; random
+// generators assign them in random order, so every labelling of a shape is equally likely.
+use super::operations::TreeError;
+use super::structure::{Tree, Node, NodeId};
+
+// SplitMix64 (Steele, Lea & Flood 2014): tiny, fast and well distributed, which is all tests need.
+#[derive(Debug, Clone)]
+pub struct SeededRng {
+    state: u64,
+}
+
+impl SeededRng {
+    pub fn new(seed: u64) -> Self {
+        SeededRng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    pub fn next_f64(&mut self) -> f64 { // Uniform in [0, 1)
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    pub fn below(&mut self, n: usize) -> usize { // Uniform in [0, n), for n > 0
+        ((self.next_u64() as u128 * n as u128) >> 64) as usize
+    }
+
+    pub fn exponential(&mut self, rate: f64) -> f64 { // Waiting time of a Poisson process with the given rate
+        -(1.0 - self.next_f64()).ln() / rate
+    }
+}
+
+// How generated leaves are named.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TaxonNaming {
+    Prefix(String), // Prefix followed by 1-based index: t1, t2, ...
+    Letters, // A, B, ..., Z, AA, AB, ...
+    Custom(Vec<String>), // The given names, which must be at least as many as the leaves
+}
+
+impl Default for TaxonNaming {
+    fn default() -> Self {
+        TaxonNaming::Prefix("t".to_string())
+    }
+}
+
+impl TaxonNaming {
+    // The first n names of the scheme.
+    pub fn names(&self, n: usize) -> Result<Vec<String>, TreeError> {
+        match self {
+            TaxonNaming::Prefix(prefix) => Ok((1..=n).map(|i| format!("{}{}", prefix, i)).collect()),
+            TaxonNaming::Letters => Ok((0..n).map(letters).collect()),
+            TaxonNaming::Custom(names) if names.len() >= n => Ok(names[..n].to_vec()),
+            TaxonNaming::Custom(names) => Err(TreeError::NotEnoughNames(names.len())),
+        }
+    }
+}
+
+fn letters(mut i: usize) -> String { // 0 -> A, 25 -> Z, 26 -> AA, like spreadsheet columns
+    let mut name = Vec::new();
+    loop {
+        name.push(b'A' + (i % 26) as u8);
+        if i < 26 {
+            break;
+        }
+        i = i / 26 - 1;
+    }
+    name.reverse();
+    String::from_utf8(name).expect("ASCII letters")
+}
+
+// Reproducible source of trees. Each call draws from the same random stream, so a sequence of calls made
+// with one seed always gives the same sequence of trees.
+#[derive(Debug, Clone)]
+pub struct TreeGenerator {
+    rng: SeededRng,
+    naming: TaxonNaming,
+}
+
+impl TreeGenerator {
+    pub fn new(seed: u64) -> Self {
+        TreeGenerator { rng: SeededRng::new(seed), naming: TaxonNaming::default() }
+    }
+
+    // Sets how leaves are named (default t1, t2, ...).
+    pub fn with_naming(mut self, naming: TaxonNaming) -> Self {
+        self.naming = naming;
+        self
+    }
+
+    pub fn rng(&mut self) -> &mut SeededRng { // The underlying random stream, e.g. for drawing parameters
+        &mut self.rng
+    }
+
+    // Yule (pure birth) tree with n leaves: every lineage splits at `birth_rate`, starting from one
+    // lineage at the root. The process is stopped after one more waiting time once n lineages exist, so
+    // the youngest cherries do not get zero-length edges.
+    pub fn yule(&mut self, n: usize, birth_rate: f64) -> Result<Tree, TreeError> {
+        self.birth_death(n, birth_rate, 0.0)
+    }
+
+    // Reconstructed birth–death tree with n extant leaves: lineages split at `birth_rate` and go extinct
+    // at `death_rate` (which must be lower), and extinct lineages are pruned from the result. A run that
+    // dies out is restarted from the same random stream.
+    pub fn birth_death(&mut self, n: usize, birth_rate: f64, death_rate: f64) -> Result<Tree, TreeError> {
+        if n == 0 {
+            return Err(TreeError::EmptyTaxonSet);
+        }
+        if !(birth_rate > 0.0 && birth_rate.is_finite()) {
+            return Err(TreeError::InvalidRate(birth_rate));
+        }
+        if !(0.0..birth_rate).contains(&death_rate) {
+            return Err(TreeError::InvalidRate(death_rate));
+        }
+
+        let mut tree = loop {
+            if let Some(tree) = self.simulate(n, birth_rate, death_rate) {
+                break tree;
+            }
+        };
+        self.name_leaves(&mut tree, true)?;
+        Ok(tree)
+    }
+
+    // One forward run; None if every lineage died out.
+    fn simulate(&mut self, n: usize, birth: f64, death: f64) -> Option<Tree> {
+        let mut tree = Tree::new();
+        tree.nodes.push(Node::default());
+        let mut start = vec![0.0]; // Time each node's lineage began
+        let mut end = vec![0.0]; // Time it split or went extinct
+        let mut active: Vec<NodeId> = vec![0];
+        let mut extinct = false;
+        let mut time = 0.0;
+
+        loop {
+            let k = active.len();
+            if k == 0 {
+                return None;
+            }
+            time += self.rng.exponential(k as f64 * (birth + death));
+            if k == n {
+                break;
+            }
+            let slot = self.rng.below(k);
+            let node = active[slot];
+            end[node] = time;
+            if self.rng.next_f64() * (birth + death) < birth {
+                let (a, b) = (tree.nodes.len(), tree.nodes.len() + 1);
+                for _ in 0..2 {
+                    tree.nodes.push(Node { parent: Some(node), ..Default::default() });
+                    start.push(time);
+                    end.push(time);
+                }
+                tree.nodes[node].children = vec![a, b];
+                active[slot] = a;
+                active.push(b);
+            } else {
+                active.swap_remove(slot);
+                extinct = true;
+            }
+        }
+
+        for &leaf in &active {
+            end[leaf] = time;
+        }
+        for id in 1..tree.nodes.len() {
+            tree.nodes[id].length_to_parent = Some(end[id] - start[id]);
+        }
+        if extinct {
+            active.sort_unstable(); // Keep leaves in creation order, independent of the removal history
+            tree = tree.restrict_to_leaves(&active).ok()?;
+        }
+        Some(tree)
+    }
+
+    // Uniformly random rooted binary topology on n leaves, without branch lengths. Leaves are added one at a
+    // time on a uniformly chosen edge, the edge above the root included, which makes every labelled
+    // topology equally likely.
+    pub fn uniform(&mut self, n: usize) -> Result<Tree, TreeError> {
+        if n == 0 {
+            return Err(TreeError::EmptyTaxonSet);
+        }
+        let mut tree = Tree::new();
+        tree.nodes.push(Node::default());
+        for _ in 1..n {
+            let below = self.rng.below(tree.nodes.len()); // The new leaf goes on the edge above this node
+            let (inner, leaf) = (tree.nodes.len(), tree.nodes.len() + 1);
+            let parent = tree.nodes[below].parent;
+            tree.nodes.push(Node { parent, children: vec![below, leaf], ..Default::default() });
+            tree.nodes.push(Node { parent: Some(inner), ..Default::default() });
+            match parent {
+                Some(p) => {
+                    let slot = tree.nodes[p].children.iter().position(|&c| c == below).expect("child of its parent");
+                    tree.nodes[p].children[slot] = inner;
+                }
+                None => tree.root = inner,
+            }
+            tree.nodes[below].parent = Some(inner);
+        }
+        self.name_leaves(&mut tree, true)?;
+        Ok(tree)
+    }
+
+    // Balanced binary tree: every internal node splits its leaves as evenly as possible, the larger half
+    // first. Leaves are named in order from left to right.
+    pub fn balanced(&mut self, n: usize) -> Result<Tree, TreeError> {
+        if n == 0 {
+            return Err(TreeError::EmptyTaxonSet);
+        }
+        let mut tree = Tree::new();
+        let mut stack: Vec<(Option<NodeId>, usize)> = vec![(None, n)]; // (parent, leaves below)
+        while let Some((parent, size)) = stack.pop() {
+            let id = tree.nodes.len();
+            tree.nodes.push(Node { parent, ..Default::default() });
+            if let Some(p) = parent {
+                tree.nodes[p].children.push(id);
+            }
+            if size > 1 {
+                stack.push((Some(id), size / 2)); // Popped second: the right, smaller half
+                stack.push((Some(id), size - size / 2));
+            }
+        }
+        self.name_leaves(&mut tree, false)?;
+        Ok(tree)
+    }
+
+    // Caterpillar (pectinate) tree ((((t1,t2),t3),t4),...): the deepest possible tree on n leaves and the
+    // worst case for recursive algorithms. Leaves are named in order from the deepest cherry upwards.
+    pub fn caterpillar(&mut self, n: usize) -> Result<Tree, TreeError> {
+        if n == 0 {
+            return Err(TreeError::EmptyTaxonSet);
+        }
+        let mut tree = Tree::new();
+        tree.nodes.push(Node::default());
+        let mut spine = 0; // Internal node still waiting for its children
+        for remaining in (2..=n).rev() {
+            let (inner, leaf) = (tree.nodes.len(), tree.nodes.len() + 1);
+            let is_cherry = remaining == 2; // Both children of the last spine node are leaves
+            tree.nodes.push(Node { parent: Some(spine), ..Default::default() });
+            tree.nodes.push(Node { parent: Some(spine), ..Default::default() });
+            tree.nodes[spine].children = vec![inner, leaf];
+            if !is_cherry {
+                spine = inner;
+            }
+        }
+        self.name_leaves(&mut tree, false)?;
+        Ok(tree)
+    }
+
+    // Labels the leaves, in preorder or in random order.
+    fn name_leaves(&mut self, tree: &mut Tree, shuffle: bool) -> Result<(), TreeError> {
+        let leaves: Vec<NodeId> = tree.preorder_iter().filter(|&id| tree.is_leaf(id)).collect();
+        let mut names = self.naming.names(leaves.len())?;
+        if shuffle {
+            for i in (1..names.len()).rev() { // Fisher–Yates
+                names.swap(i, self.rng.below(i + 1));
+            }
+        }
+        for (leaf, name) in leaves.into_iter().zip(names) {
+            tree.nodes[leaf].label = Some(name);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,244 @@
+// This module extracts the bipartitions (splits) of a tree as fixed-width bitsets over taxon ids.
+// Every edge of an unrooted tree divides its leaves in two; the non-trivial splits (both sides with at
+// least two taxa) identify the tree's topology, so they are the building block for RF distances,
+// split frequencies and consensus trees. Trees must be bound to a TaxonSet first (Tree::bind_taxa).
+use super::structure::Tree;
+use super::taxa::TaxonId;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+// Set of taxon ids stored as bits in 64-bit words. The width (number of taxa) is fixed at creation.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Bitset {
+    words: Vec<u64>, // Bit i of the set lives in words[i / 64]
+    n_bits: usize, // Width of the set, i.e. the number of taxa it ranges over
+}
+
+impl Bitset {
+    pub fn new(n_bits: usize) -> Self { // Empty set over n_bits taxa
+        Bitset { words: vec![0; n_bits.div_ceil(64)], n_bits }
+    }
+
+    pub fn from_ids(n_bits: usize, ids: impl IntoIterator<Item = TaxonId>) -> Self { // Set containing the given ids
+        let mut set = Bitset::new(n_bits);
+        for id in ids {
+            set.insert(id);
+        }
+        set
+    }
+
+    pub fn width(&self) -> usize { // Number of taxa the set ranges over
+        self.n_bits
+    }
+
+    pub fn insert(&mut self, id: TaxonId) { // Ids beyond the width are ignored, like in remove and contains
+        if id < self.n_bits {
+            self.words[id / 64] |= 1 << (id % 64);
+        }
+    }
+
+    pub fn remove(&mut self, id: TaxonId) {
+        if id < self.n_bits {
+            self.words[id / 64] &= !(1 << (id % 64));
+        }
+    }
+
+    pub fn contains(&self, id: TaxonId) -> bool {
+        id < self.n_bits && self.words[id / 64] & (1 << (id % 64)) != 0
+    }
+
+    pub fn count(&self) -> usize { // Number of taxa in the set
+        self.words.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|&w| w == 0)
+    }
+
+    pub fn union_with(&mut self, other: &Bitset) { // self |= other
+        for (a, b) in self.words.iter_mut().zip(&other.words) {
+            *a |= b;
+        }
+    }
+
+    pub fn intersect_with(&mut self, other: &Bitset) { // self &= other
+        for (a, b) in self.words.iter_mut().zip(&other.words) {
+            *a &= b;
+        }
+    }
+
+    pub fn difference(&self, other: &Bitset) -> Bitset { // self \ other
+        Bitset {
+            words: self.words.iter().zip(&other.words).map(|(a, b)| a & !b).collect(),
+            n_bits: self.n_bits,
+        }
+    }
+
+    pub fn is_subset(&self, other: &Bitset) -> bool { // Every taxon of self is in other
+        self.words.iter().zip(&other.words).all(|(a, b)| a & !b == 0)
+    }
+
+    pub fn is_disjoint(&self, other: &Bitset) -> bool { // No taxon in common
+        self.words.iter().zip(&other.words).all(|(a, b)| a & b == 0)
+    }
+
+    pub fn first(&self) -> Option<TaxonId> { // Smallest taxon id in the set
+        self.words.iter().enumerate().find(|(_, w)| **w != 0).map(|(i, w)| i * 64 + w.trailing_zeros() as usize)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = TaxonId> + '_ { // Taxon ids in increasing order
+        (0..self.n_bits).filter(move |&id| self.contains(id))
+    }
+}
+
+// A bipartition of the tree's taxa. `bits` holds the side that does not contain the tree's smallest
+// taxon id, so the same split always has the same bitset whichever way it was found. Equality, hashing
+// and ordering look at the bitset only; length and support are carried along as edge data.
+#[derive(Debug, Clone)]
+pub struct Split {
+    pub bits: Bitset, // Canonical side of the split
+    pub length: Option<f64>, // Length of the edge inducing the split
+    pub support: Option<f64>, // Support of the edge inducing the split
+}
+
+impl PartialEq for Split {
+    fn eq(&self, other: &Self) -> bool {
+        self.bits == other.bits
+    }
+}
+
+impl Eq for Split {}
+
+impl Hash for Split {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.bits.hash(state);
+    }
+}
+
+impl PartialOrd for Split {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Split {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.bits.cmp(&other.bits)
+    }
+}
+
+impl Split {
+    // Puts one side of a split (relative to the taxa present in `all`) into canonical form.
+    pub fn canonical(side: Bitset, all: &Bitset, length: Option<f64>, support: Option<f64>) -> Split {
+        let bits = match all.first() {
+            Some(lowest) if side.contains(lowest) => all.difference(&side), // Keep the side without the lowest taxon
+            _ => side,
+        };
+        Split { bits, length, support }
+    }
+
+    pub fn size(&self) -> usize { // Number of taxa on the canonical side
+        self.bits.count()
+    }
+
+    pub fn is_compatible(&self, other: &Split, all: &Bitset) -> bool { // Two splits can coexist in one tree
+        let (a, b) = (&self.bits, &other.bits);
+        let a_rest = all.difference(a);
+        let b_rest = all.difference(b);
+        a.is_disjoint(b) || a.is_subset(b) || b.is_subset(a) || a_rest.is_disjoint(&b_rest)
+    }
+}
+
+// Bipartitions of trees bound to a TaxonSet
+impl Tree {
+    // Returns the set of taxon ids below each node, as bitsets of width n_taxa (index = NodeId).
+    // Leaves without a taxon id, or with an id of n_taxa or more (taxa added to the set later), contribute nothing.
+    pub fn clade_bitsets(&self, n_taxa: usize) -> Vec<Bitset> {
+        let mut clades = vec![Bitset::new(n_taxa); self.nodes.len()];
+
+        for node in self.postorder() { // Postorder ensures children are computed before their parent
+            let mut bits = Bitset::new(n_taxa);
+            if let Some(t) = self.nodes[node].taxon {
+                bits.insert(t);
+            }
+            for &child in &self.nodes[node].children {
+                bits.union_with(&clades[child]);
+            }
+            clades[node] = bits;
+        }
+        clades
+    }
+
+    // Returns all taxa of the tree as a bitset of width n_taxa.
+    pub fn taxon_bitset(&self, n_taxa: usize) -> Bitset {
+        Bitset::from_ids(n_taxa, self.taxon_ids())
+    }
+
+    // Returns the non-trivial splits of the tree (both sides with at least two taxa), sorted by bitset.
+    // The two edges below a bifurcating root induce the same split; they are merged into one split whose
+    // length is the sum of both edges and whose support is taken from whichever edge has one.
+    pub fn splits(&self, n_taxa: usize) -> Vec<Split> {
+        let all = self.taxon_bitset(n_taxa);
+        self.restricted_splits(n_taxa, &all, false)
+    }
+
+    // Returns the splits of the subtree induced by the taxa in `mask` (taxa of the tree outside the mask
+    // are ignored), without building that subtree. Edges that collapse onto the same restricted split, such
+    // as the path left after pruning, are merged by summing their lengths. With `trivial` set, the pendant
+    // splits (one taxon against the rest) are included too, which is what weighted comparisons need.
+    pub fn restricted_splits(&self, n_taxa: usize, mask: &Bitset, trivial: bool) -> Vec<Split> {
+        let clades = self.clade_bitsets(n_taxa);
+        let mut all = clades[self.root].clone();
+        all.intersect_with(mask); // Taxa that both are in the tree and are kept
+        let total = all.count();
+        let min_side = if trivial { 1 } else { 2 };
+        let mut by_bits: HashMap<Bitset, Split> = HashMap::new();
+
+        for node in self.preorder() {
+            if node == self.root {
+                continue; // The root has no edge above it
+            }
+            let mut side = clades[node].clone();
+            side.intersect_with(mask);
+            let size = side.count();
+            if size < min_side || total - size < min_side {
+                continue; // Trivial split, or an edge leading only to ignored taxa
+            }
+
+            let data = &self.nodes[node];
+            let split = Split::canonical(side, &all, data.length_to_parent, data.support);
+            match by_bits.get_mut(&split.bits) {
+                Some(existing) => { // Another edge inducing the same split (root edges, pruned paths)
+                    existing.length = match (existing.length, split.length) {
+                        (Some(a), Some(b)) => Some(a + b),
+                        (a, b) => a.or(b),
+                    };
+                    existing.support = existing.support.or(split.support);
+                }
+                None => {
+                    by_bits.insert(split.bits.clone(), split);
+                }
+            }
+        }
+
+        let mut splits: Vec<Split> = by_bits.into_values().collect();
+        splits.sort();
+        splits
+    }
+}
+
+// Counts in how many of the trees each non-trivial split occurs. All trees must be bound to the same
+// TaxonSet of n_taxa taxa. Splits are compared over each tree's own taxa, so trees should share a taxon set.
+pub fn split_frequencies<'a, I>(trees: I, n_taxa: usize) -> HashMap<Bitset, usize>
+where
+    I: IntoIterator<Item = &'a Tree>,
+{
+    let mut counts = HashMap::new();
+    for tree in trees {
+        for split in tree.splits(n_taxa) {
+            *counts.entry(split.bits).or_insert(0) += 1;
+        }
+    }
+    counts
+}
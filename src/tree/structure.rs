@@ -1,24 +1,68 @@
 // This module defines the data structures for representing a tree. 
 // The tree is represented as a vector of nodes, where each node has a parent and a list of children. 
 // The root node is identified by its index in the vector.
+use super::lca::LcaIndex;
+use super::taxa::TaxonId;
+use std::collections::BTreeMap;
+
 pub type NodeId = usize;
 
+// Typed value of a node annotation such as S=human, B=95 or posterior=0.98.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetaValue {
+    Int(i64),
+    Float(f64),
+    Str(String),
+    List(Vec<MetaValue>), // BEAST ranges and sets, e.g. height_95%_HPD={1.2,3.4}
+}
+
+impl MetaValue {
+    pub fn as_f64(&self) -> Option<f64> { // Numeric value of an Int or Float annotation
+        match self {
+            MetaValue::Int(i) => Some(*i as f64),
+            MetaValue::Float(x) => Some(*x),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> { // Text of a Str annotation
+        match self {
+            MetaValue::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
 
 // The Tree struct represents the entire tree, containing a vector of nodes and the index of the root node.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Tree {
     pub nodes: Vec<Node>, // Vector of nodes in the tree
     pub root: NodeId, // Index of the root node in the nodes vector
+    pub lca_index: Option<LcaIndex>, // Optional constant-time LCA index (see Tree::build_lca_index); None when building trees by hand
+    pub support_scale: Option<SupportScale>, // Scale the support labels were read on (set by labels_to_support), used again when writing
 }
 
 // The Node struct represents a single node in the tree, containing an optional parent node ID, a list of child node IDs, an optional label,
 // and an optional branch length.
-#[derive(Debug)]
+#[derive(Debug, Clone, Default)]
 pub struct Node {
     pub parent: Option<NodeId>, // Optional parent node ID (None for the root node)
     pub children: Vec<NodeId>, // List of child node IDs
     pub label: Option<String>, // Optional label for the node
+    pub taxon: Option<TaxonId>, // Taxon id of a leaf label in a shared TaxonSet (set by Tree::bind_taxa)
     pub length_to_parent: Option<f64>, // Optional branch length to the parent node
+    pub support: Option<f64>, // Support of the edge to the parent node (bootstrap / posterior), as a proportion in [0, 1]
+    pub comments: Vec<String>, // Bracketed [comments] attached to the node in the source file, without the brackets
+    pub metadata: BTreeMap<String, MetaValue>, // Key/value annotations parsed from NHX [&&NHX:...] and BEAST [&...] comments
+}
+
+// How numeric internal labels express branch support.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SupportScale {
+    Percent, // Labels are percentages (e.g. bootstrap 95), stored divided by 100
+    Proportion, // Labels are already proportions (e.g. posterior 0.87)
+    Auto, // Percent if any numeric internal label exceeds 1, otherwise proportion
 }
 
 // Implementation of the Tree struct, providing a constructor to create a new tree with an empty vector of nodes and a root index of 0.
@@ -27,15 +71,23 @@ impl Tree {
         Tree {
             nodes: Vec::new(),
             root: 0,
+            lca_index: None,
+            support_scale: None,
         }
     }
 }
 
+impl Default for Tree {
+    fn default() -> Self {
+        Tree::new()
+    }
+}
+
 
 // Fully AI generated code for printing the tree structure in an ASCII format. This method recursively prints each node and its children, using indentation and connectors to visually represent the tree structure. The label and branch length (if available) are also displayed for each node.
 impl Tree {
     pub fn print_ascii(&self) {
-        self.print_node_branches(self.root, "", true);
+        print!("{}", self.to_ascii());
     }
 
     pub fn is_leaf(&self, node_id: NodeId) -> bool { // A node is a leaf if it has no children
@@ -60,31 +112,11 @@ impl Tree {
     }
 
     pub fn preorder(&self) -> Vec<NodeId> { // Returns a vector of node IDs in preorder traversal (visit the current node before its children)
-        let mut result = Vec::new(); // Create a mutable vector to store the result of the preorder traversal
-        self.preorder_rec(self.root, &mut result); // Call the recursive helper function to perform the preorder traversal starting from the root node
-        result // Return the resulting vector of node IDs in preorder traversal
-    }
-
-    fn preorder_rec(&self, node_id: NodeId, result: &mut Vec<NodeId>) { // Recursive helper function for preorder traversal, takes the current node ID and a mutable reference to the result vector
-        result.push(node_id); // Add the current node ID to the result vector (visit the current node)
-
-        for &child in &self.nodes[node_id].children { // Iterate over the child node IDs of the current node
-            self.preorder_rec(child, result); // Recursively call the helper function for each child node ID, passing the same result vector to accumulate the traversal order
-        }
+        self.preorder_iter().collect() // Iterative, so deep trees cannot overflow the stack (see traversal.rs)
     }
 
     pub fn postorder(&self) -> Vec<NodeId> { // Returns a vector of node IDs in postorder traversal (visit the children before the current node)
-    let mut result = Vec::new(); // Create a mutable vector to store the result of the postorder traversal
-    self.postorder_rec(self.root, &mut result); // Call the recursive helper function to perform the postorder traversal starting from the root node
-    result // Return the resulting vector of node IDs in postorder traversal
-    }
-
-    fn postorder_rec(&self, node_id: NodeId, result: &mut Vec<NodeId>) { // Recursive helper function for postorder traversal, takes the current node ID and a mutable reference to the result vector
-        for &child in &self.nodes[node_id].children { // Iterate over the child node IDs of the current node
-            self.postorder_rec(child, result); // Recursively call the helper function for each child node ID, passing the same result vector to accumulate the traversal order
-        }
-
-        result.push(node_id); // Add the current node ID to the result vector after visiting all its children (visit the current node)
+        self.postorder_iter().collect() // Iterative, so deep trees cannot overflow the stack (see traversal.rs)
     }
 
     pub fn ancestors(&self, node_id: NodeId) -> Vec<NodeId> { // Returns a vector of node IDs that are ancestors of the given node ID, starting from the given node and going up to the root
@@ -100,6 +132,10 @@ impl Tree {
     }
 
     pub fn lca(&self, mut a: NodeId, mut b: NodeId) -> Option<NodeId> { // Returns the node ID of the lowest common ancestor (LCA) of the two given node IDs, or None if either node ID is invalid
+        if let Some(index) = self.lca_index() { // Constant-time answer when the index has been built
+            return index.lca(a, b);
+        }
+
         let mut depth_a = self.depth(a); // Get the depth of node a in the tree (number of edges from a to the root)
         let mut depth_b = self.depth(b); // Get the depth of node b in the tree (number of edges from b to the root)
 
@@ -127,6 +163,18 @@ impl Tree {
     self.nodes[node_id].label.as_deref() // Return the label of the node with the given node ID as an Option<&str>, converting from Option<String> using as_deref()
     }
 
+    pub fn node_comments(&self, node_id: NodeId) -> &[String] { // Returns the [comments] attached to the node with the given node ID
+    &self.nodes[node_id].comments
+    }
+
+    pub fn metadata(&self, node_id: NodeId, key: &str) -> Option<&MetaValue> { // Returns the annotation with the given key on the node, if present
+    self.nodes[node_id].metadata.get(key)
+    }
+
+    pub fn support(&self, node_id: NodeId) -> Option<f64> { // Returns the support of the edge above the node, if known
+    self.nodes[node_id].support
+    }
+
     pub fn taxa(&self) -> Vec<&str> { // Returns a vector of labels for the leaf nodes (taxa) in the tree, filtering out any nodes that do not have labels
     self.leaves() // Get the node IDs of the leaf nodes in the tree
         .into_iter() // Convert the leaf node IDs into an iterator
@@ -146,56 +194,74 @@ impl Tree {
     depth // Return the calculated depth of the node with the given node ID, which is the number of edges from the node to the root
     }   
 
-    // AI generated ASCII tree printing method
-    fn print_node_branches(&self, node_id: NodeId, prefix: &str, is_root: bool) {
-        let node = &self.nodes[node_id];
-        let label = node.label.as_deref().unwrap_or("internal");
-        let length = match node.length_to_parent {
-            Some(l) => format!(" ({:.3})", l),
-            None => String::new(),
-        };
-
-        // Print the current node
-        if is_root {
-            println!("{}{}", label, length);
-        } else {
-            println!("{}{}{}", prefix, label, length);
+    // AI generated ASCII tree printing method, made iterative: the lines still to print are kept on an
+    // explicit stack (pushed in reverse), so deep trees cannot overflow the call stack.
+    pub fn to_ascii(&self) -> String {
+        enum Step {
+            Node(NodeId, String, bool), // Node to print, its prefix, and whether it is the root
+            Line(String), // Branch connector line
         }
 
-        let child_count = node.children.len();
-        
-        if child_count == 0 {
-            return;
+        let mut out = String::new();
+        if self.nodes.is_empty() {
+            return out;
         }
+        let mut stack = vec![Step::Node(self.root, String::new(), true)];
+
+        while let Some(step) = stack.pop() {
+            let (node_id, prefix, is_root) = match step {
+                Step::Line(line) => {
+                    out.push_str(&line);
+                    out.push('\n');
+                    continue;
+                }
+                Step::Node(node_id, prefix, is_root) => (node_id, prefix, is_root),
+            };
+            let node = &self.nodes[node_id];
+            let label = node.label.as_deref().unwrap_or("internal");
+            let length = match node.length_to_parent {
+                Some(l) => format!(" ({:.3})", l),
+                None => String::new(),
+            };
+
+            // Print the current node
+            if is_root {
+                out.push_str(&format!("{}{}\n", label, length));
+            } else {
+                out.push_str(&format!("{}{}{}\n", prefix, label, length));
+            }
+
+            let child_count = node.children.len();
+            if child_count == 0 {
+                continue;
+            }
 
-        // Calculate spacing for the branches
-        let spacing = " ".repeat(label.len() + length.len());
-        
-        // Print the branch connectors
-        if child_count == 1 {
-            let child_prefix = format!("{}|", spacing);
-            println!("{}", child_prefix);
-            self.print_node_branches(node.children[0], &format!("{}  ", spacing), false);
-        } else if child_count == 2 {
-            // Print branches for two children (the classic look)
-            let mid_spacing = " ".repeat(spacing.len());
-            let branch_line = format!("{}/{}\\", spacing, mid_spacing);
-            println!("{}", branch_line);
-            
-            // Print left child
-            self.print_node_branches(node.children[0], &format!("{}  ", spacing), false);
-            
-            // Print right child  
-            let right_prefix = format!("{}{}", spacing, " ".repeat(spacing.len() + 2));
-            self.print_node_branches(node.children[1], &right_prefix, false);
-        } else {
-            // For more than 2 children, use a simpler format
-            for (i, &child) in node.children.iter().enumerate() {
-                let connector = if i == child_count - 1 { "\\" } else { "|" };
-                let child_prefix = format!("{}{}-- ", spacing, connector);
-                println!("{}", child_prefix);
-                self.print_node_branches(child, &format!("{}    ", spacing), false);
+            // Calculate spacing for the branches
+            let spacing = " ".repeat(label.len() + length.len());
+            let mut steps = Vec::new(); // Steps for the children, in printing order
+
+            // Print the branch connectors
+            if child_count == 1 {
+                steps.push(Step::Line(format!("{}|", spacing)));
+                steps.push(Step::Node(node.children[0], format!("{}  ", spacing), false));
+            } else if child_count == 2 {
+                // Branches for two children (the classic look): left child, then right child
+                let mid_spacing = " ".repeat(spacing.len());
+                steps.push(Step::Line(format!("{}/{}\\", spacing, mid_spacing)));
+                steps.push(Step::Node(node.children[0], format!("{}  ", spacing), false));
+                let right_prefix = format!("{}{}", spacing, " ".repeat(spacing.len() + 2));
+                steps.push(Step::Node(node.children[1], right_prefix, false));
+            } else {
+                // For more than 2 children, use a simpler format
+                for (i, &child) in node.children.iter().enumerate() {
+                    let connector = if i == child_count - 1 { "\\" } else { "|" };
+                    steps.push(Step::Line(format!("{}{}-- ", spacing, connector)));
+                    steps.push(Step::Node(child, format!("{}    ", spacing), false));
+                }
             }
+            stack.extend(steps.into_iter().rev());
         }
+
+        out
     }
 }
\ No newline at end of file
@@ -0,0 +1,107 @@
+// This module defines the taxon namespace shared by a collection of trees.
+// Leaf labels are interned once into dense integer ids (0..n), so trees parsed against the same TaxonSet
+// can be compared by id instead of by string, and taxon ids can index arrays and bitsets directly.
+use super::structure::{Tree, NodeId};
+use super::operations::TreeError;
+use std::collections::HashMap;
+
+pub type TaxonId = usize;
+
+#[derive(Debug, Clone, Default)]
+pub struct TaxonSet {
+    names: Vec<String>, // Name of each taxon, indexed by TaxonId
+    ids: HashMap<String, TaxonId>, // Reverse lookup from name to TaxonId
+}
+
+impl TaxonSet {
+    pub fn new() -> Self {
+        TaxonSet::default()
+    }
+
+    // Builds a set from a list of names, assigning ids in the order given (duplicates share an id).
+    pub fn from_names<I, S>(names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut taxa = TaxonSet::new();
+        for name in names {
+            taxa.intern(name.as_ref());
+        }
+        taxa
+    }
+
+    // Returns the id of the name, adding it to the set if it is new.
+    pub fn intern(&mut self, name: &str) -> TaxonId {
+        if let Some(&id) = self.ids.get(name) {
+            return id;
+        }
+        let id = self.names.len(); // Ids are dense: the next free index
+        self.names.push(name.to_string());
+        self.ids.insert(name.to_string(), id);
+        id
+    }
+
+    pub fn id(&self, name: &str) -> Option<TaxonId> { // Looks up the id of a name without adding it
+        self.ids.get(name).copied()
+    }
+
+    pub fn name(&self, id: TaxonId) -> Option<&str> { // Looks up the name of an id
+        self.names.get(id).map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize { // Number of taxa in the set
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    pub fn names(&self) -> &[String] { // All names, in id order
+        &self.names
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (TaxonId, &str)> { // (id, name) pairs in id order
+        self.names.iter().enumerate().map(|(id, name)| (id, name.as_str()))
+    }
+}
+
+// Taxon ids of the leaves of a tree
+impl Tree {
+    // Interns the labels of all leaves into the taxon set and records the resulting id on each leaf.
+    // Call this with the same TaxonSet for every tree of a collection so their ids agree.
+    pub fn bind_taxa(&mut self, taxa: &mut TaxonSet) {
+        for node in self.nodes.iter_mut().filter(|n| n.children.is_empty()) {
+            node.taxon = node.label.as_deref().map(|label| taxa.intern(label));
+        }
+    }
+
+    pub fn taxon(&self, node_id: NodeId) -> Option<TaxonId> { // Returns the taxon id of a leaf bound with bind_taxa
+        self.nodes[node_id].taxon
+    }
+
+    // Checks that every leaf carries a taxon id. Comparisons by taxon id would otherwise skip unbound
+    // leaves, and two unbound trees would look identical.
+    pub fn check_bound(&self) -> Result<(), TreeError> {
+        match self.leaves().into_iter().find(|&leaf| self.nodes[leaf].taxon.is_none()) {
+            Some(leaf) => Err(TreeError::UnboundLeaf(leaf)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn taxon_ids(&self) -> Vec<TaxonId> { // Returns the taxon ids of all bound leaves, in node order
+        self.leaves().into_iter().filter_map(|id| self.nodes[id].taxon).collect()
+    }
+
+    // Returns, for each taxon id below `n_taxa`, the leaf carrying it (None if the tree lacks that taxon).
+    pub fn leaves_by_taxon(&self, n_taxa: usize) -> Vec<Option<NodeId>> {
+        let mut map = vec![None; n_taxa];
+        for leaf in self.leaves() {
+            if let Some(t) = self.nodes[leaf].taxon.filter(|&t| t < n_taxa) {
+                map[t] = Some(leaf);
+            }
+        }
+        map
+    }
+}
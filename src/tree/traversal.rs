@@ -0,0 +1,79 @@
+// This module provides lazy, non-recursive traversals of a tree. Each iterator keeps its own explicit stack
+// or queue, so very deep trees (e.g. caterpillars with hundreds of thousands of leaves) cannot overflow the
+// call stack. Children are visited in the order of their `children` vector.
+use super::structure::{Tree, NodeId};
+use std::collections::VecDeque;
+
+// Nodes in preorder: every node before its children.
+pub struct PreorderIter<'a> {
+    tree: &'a Tree,
+    stack: Vec<NodeId>, // Nodes still to visit, the next one on top
+}
+
+impl Iterator for PreorderIter<'_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let node = self.stack.pop()?;
+        self.stack.extend(self.tree.nodes[node].children.iter().rev()); // First child ends up on top
+        Some(node)
+    }
+}
+
+// Nodes in postorder: every node after all of its children.
+pub struct PostorderIter<'a> {
+    tree: &'a Tree,
+    stack: Vec<(NodeId, usize)>, // Path from the root to the current node, with the index of the next child to enter
+}
+
+impl Iterator for PostorderIter<'_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        loop {
+            let (node, next) = self.stack.last_mut()?;
+            match self.tree.nodes[*node].children.get(*next) {
+                Some(&child) => {
+                    *next += 1;
+                    self.stack.push((child, 0)); // Descend before emitting anything
+                }
+                None => return self.stack.pop().map(|(node, _)| node), // All children done
+            }
+        }
+    }
+}
+
+// Nodes in level order (breadth first): the root, then all nodes at depth 1, and so on.
+pub struct LevelorderIter<'a> {
+    tree: &'a Tree,
+    queue: VecDeque<NodeId>,
+}
+
+impl Iterator for LevelorderIter<'_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let node = self.queue.pop_front()?;
+        self.queue.extend(self.tree.nodes[node].children.iter());
+        Some(node)
+    }
+}
+
+// Lazy traversals starting at the root. An empty tree yields nothing.
+impl Tree {
+    pub fn preorder_iter(&self) -> PreorderIter<'_> {
+        PreorderIter { tree: self, stack: self.start().into_iter().collect() }
+    }
+
+    pub fn postorder_iter(&self) -> PostorderIter<'_> {
+        PostorderIter { tree: self, stack: self.start().map(|root| (root, 0)).into_iter().collect() }
+    }
+
+    pub fn levelorder_iter(&self) -> LevelorderIter<'_> {
+        LevelorderIter { tree: self, queue: self.start().into_iter().collect() }
+    }
+
+    fn start(&self) -> Option<NodeId> { // The root, if the tree has any nodes
+        (self.root < self.nodes.len()).then_some(self.root)
+    }
+}
@@ -0,0 +1,169 @@
+// This module checks the structural invariants of a Tree. The fields of Tree and Node are public, so code
+// that edits them directly (or builds trees by hand) can leave links that disagree, cycles or orphaned
+// nodes; validate reports all such problems instead of letting later algorithms loop or miscount.
+// Duplicate and missing leaf labels are reported too, as they silently corrupt per-taxon statistics such
+// as quartet counts.
+use super::structure::{Tree, NodeId};
+use std::collections::HashMap;
+use std::fmt;
+
+// One violated invariant, with the nodes involved.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TreeIssue {
+    EmptyTree, // The tree has no nodes
+    InvalidRoot(NodeId), // The root index is out of range
+    RootHasParent(NodeId), // The root has a parent
+    DanglingChild { parent: NodeId, child: NodeId }, // A child id that is out of range
+    DanglingParent { node: NodeId, parent: NodeId }, // A parent id that is out of range
+    DuplicateChild { parent: NodeId, child: NodeId }, // The same child listed twice by one parent
+    ParentMismatch { node: NodeId, listed_by: NodeId }, // Listed as a child of `listed_by`, but its parent field says otherwise
+    NotAChildOfParent { node: NodeId, parent: NodeId }, // Its parent does not list it as a child
+    Cycle(NodeId), // Following parent links from this node loops forever
+    Unreachable(NodeId), // Not reachable from the root
+    UnlabeledLeaf(NodeId), // A leaf without a label
+    DuplicateLabel { label: String, nodes: Vec<NodeId> }, // Several leaves share a label
+    InvalidBranchLength(NodeId), // Negative, infinite or NaN branch length
+}
+
+impl TreeIssue {
+    // The node the issue is best reported at (the first one for duplicate labels).
+    pub fn node(&self) -> Option<NodeId> {
+        match self {
+            TreeIssue::EmptyTree => None,
+            TreeIssue::InvalidRoot(_) => None,
+            TreeIssue::RootHasParent(n) | TreeIssue::Cycle(n) | TreeIssue::Unreachable(n) => Some(*n),
+            TreeIssue::UnlabeledLeaf(n) | TreeIssue::InvalidBranchLength(n) => Some(*n),
+            TreeIssue::DanglingChild { parent, .. } | TreeIssue::DuplicateChild { parent, .. } => Some(*parent),
+            TreeIssue::DanglingParent { node, .. } => Some(*node),
+            TreeIssue::ParentMismatch { node, .. } | TreeIssue::NotAChildOfParent { node, .. } => Some(*node),
+            TreeIssue::DuplicateLabel { nodes, .. } => nodes.get(1).copied(), // The first repeat
+        }
+    }
+}
+
+impl fmt::Display for TreeIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TreeIssue::EmptyTree => write!(f, "tree has no nodes"),
+            TreeIssue::InvalidRoot(r) => write!(f, "root {} does not exist", r),
+            TreeIssue::RootHasParent(r) => write!(f, "root {} has a parent", r),
+            TreeIssue::DanglingChild { parent, child } => write!(f, "node {} lists missing child {}", parent, child),
+            TreeIssue::DanglingParent { node, parent } => write!(f, "node {} has missing parent {}", node, parent),
+            TreeIssue::DuplicateChild { parent, child } => write!(f, "node {} lists child {} more than once", parent, child),
+            TreeIssue::ParentMismatch { node, listed_by } => write!(f, "node {} is a child of {} but its parent link disagrees", node, listed_by),
+            TreeIssue::NotAChildOfParent { node, parent } => write!(f, "node {} is not among the children of its parent {}", node, parent),
+            TreeIssue::Cycle(n) => write!(f, "parent links from node {} form a cycle", n),
+            TreeIssue::Unreachable(n) => write!(f, "node {} is not reachable from the root", n),
+            TreeIssue::UnlabeledLeaf(n) => write!(f, "leaf {} has no label", n),
+            TreeIssue::DuplicateLabel { label, nodes } => write!(f, "label '{}' is used by {} leaves", label, nodes.len()),
+            TreeIssue::InvalidBranchLength(n) => write!(f, "node {} has an invalid branch length", n),
+        }
+    }
+}
+
+// Invariant checking
+impl Tree {
+    // Returns every problem found, in a deterministic order; an empty list means the tree is well formed.
+    pub fn validate(&self) -> Vec<TreeIssue> {
+        let n = self.nodes.len();
+        let mut issues = Vec::new();
+        if n == 0 {
+            return vec![TreeIssue::EmptyTree];
+        }
+        if self.root >= n {
+            issues.push(TreeIssue::InvalidRoot(self.root));
+        } else if self.nodes[self.root].parent.is_some() {
+            issues.push(TreeIssue::RootHasParent(self.root));
+        }
+
+        // Parent and child links must agree in both directions. Both checks use per-node marks rather than
+        // searching child lists, so validation stays linear even at nodes of very high degree.
+        let mut listed_by_parent = vec![false; n]; // The node's parent lists it among its children
+        for (id, node) in self.nodes.iter().enumerate() {
+            for &child in node.children.iter().filter(|&&child| child < n) {
+                if self.nodes[child].parent == Some(id) {
+                    listed_by_parent[child] = true;
+                }
+            }
+        }
+        let mut last_listed_by = vec![usize::MAX; n]; // Last node found listing each node as a child
+        for (id, node) in self.nodes.iter().enumerate() {
+            for &child in &node.children {
+                if child >= n {
+                    issues.push(TreeIssue::DanglingChild { parent: id, child });
+                } else if last_listed_by[child] == id {
+                    issues.push(TreeIssue::DuplicateChild { parent: id, child });
+                } else {
+                    last_listed_by[child] = id;
+                    if self.nodes[child].parent != Some(id) {
+                        issues.push(TreeIssue::ParentMismatch { node: child, listed_by: id });
+                    }
+                }
+            }
+            match node.parent {
+                Some(p) if p >= n => issues.push(TreeIssue::DanglingParent { node: id, parent: p }),
+                Some(p) if !listed_by_parent[id] => issues.push(TreeIssue::NotAChildOfParent { node: id, parent: p }),
+                _ => {}
+            }
+            if node.length_to_parent.is_some_and(|l| !l.is_finite() || l < 0.0) {
+                issues.push(TreeIssue::InvalidBranchLength(id));
+            }
+        }
+
+        // Cycles along parent links: 1 = on the current walk, 2 = known to end at a node without parent.
+        let mut state = vec![0u8; n];
+        for start in 0..n {
+            let mut walk = Vec::new();
+            let mut current = Some(start);
+            while let Some(id) = current.filter(|&id| id < n && state[id] != 2) {
+                if state[id] == 1 {
+                    issues.push(TreeIssue::Cycle(id)); // Reported once, at the node where the loop closes
+                    break;
+                }
+                state[id] = 1;
+                walk.push(id);
+                current = self.nodes[id].parent;
+            }
+            for id in walk {
+                state[id] = 2;
+            }
+        }
+
+        // Reachability from the root through child links, guarding against revisits.
+        let mut reached = vec![false; n];
+        if self.root < n {
+            let mut stack = vec![self.root];
+            reached[self.root] = true;
+            while let Some(id) = stack.pop() {
+                for &child in &self.nodes[id].children {
+                    if child < n && !reached[child] {
+                        reached[child] = true;
+                        stack.push(child);
+                    }
+                }
+            }
+        }
+        issues.extend((0..n).filter(|&id| !reached[id]).map(TreeIssue::Unreachable));
+
+        // Leaf labels must be present and unique.
+        let mut by_label: HashMap<&str, Vec<NodeId>> = HashMap::new();
+        for id in (0..n).filter(|&id| reached[id] && self.nodes[id].children.is_empty()) {
+            match self.node_label(id) {
+                Some(label) => by_label.entry(label).or_default().push(id),
+                None => issues.push(TreeIssue::UnlabeledLeaf(id)),
+            }
+        }
+        let mut duplicates: Vec<TreeIssue> = by_label.into_iter()
+            .filter(|(_, nodes)| nodes.len() > 1)
+            .map(|(label, nodes)| TreeIssue::DuplicateLabel { label: label.to_string(), nodes })
+            .collect();
+        duplicates.sort_by_key(|issue| issue.node());
+        issues.extend(duplicates);
+
+        issues
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.validate().is_empty()
+    }
+}
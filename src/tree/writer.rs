@@ -0,0 +1,154 @@
+// This module writes trees back out in Newick format.
+// Output produced with the default options parses back (with parse_nwk) to the same topology, labels,
+// branch lengths and comments, so pruned, rerooted or consensus trees can be saved and reloaded.
+
+use super::structure::{Tree, NodeId, SupportScale};
+use std::io::{self, Write};
+
+// Options controlling what is written and how branch lengths are formatted.
+#[derive(Debug, Clone)]
+pub struct NewickOptions {
+    pub precision: Option<usize>, // Digits after the decimal point for branch lengths; None writes the shortest exact representation
+    pub internal_labels: bool, // Write labels of internal nodes (e.g. clade names or support values)
+    pub branch_lengths: bool, // Write ':length' after each node that has one
+    pub comments: bool, // Write the [comments] kept on nodes by the parser
+}
+
+impl Default for NewickOptions {
+    fn default() -> Self {
+        NewickOptions {
+            precision: None,
+            internal_labels: true,
+            branch_lengths: true,
+            comments: true,
+        }
+    }
+}
+
+impl NewickOptions {
+    // Options for a bare topology: leaf labels only.
+    pub fn topology_only() -> Self {
+        NewickOptions {
+            precision: None,
+            internal_labels: false,
+            branch_lengths: false,
+            comments: false,
+        }
+    }
+}
+
+impl Tree {
+    // Returns the tree as a Newick string (terminated by ';', without a newline) using the default options.
+    pub fn to_newick(&self) -> String {
+        self.to_newick_with(&NewickOptions::default())
+    }
+
+    // Returns the tree as a Newick string using the given options.
+    pub fn to_newick_with(&self, options: &NewickOptions) -> String {
+        let mut out = Vec::new();
+        self.write_newick(&mut out, options).expect("writing to a Vec cannot fail"); // In-memory writes are infallible
+        out.pop(); // Drop the newline written after ';'
+        String::from_utf8(out).expect("labels and comments are valid UTF-8") // Everything written comes from Strings
+    }
+
+    // Writes the tree in Newick format followed by ";\n" to any writer (file, stdout, buffer), so
+    // writing several trees in a row gives the one-tree-per-line layout read by NewickReader.
+    // The tree is walked with an explicit stack so very deep trees cannot overflow the call stack.
+    pub fn write_newick<W: Write>(&self, out: &mut W, options: &NewickOptions) -> io::Result<()> {
+        if self.nodes.is_empty() { // An empty tree has nothing but the terminator
+            return writeln!(out, ";");
+        }
+
+        enum Step { // Work items for the explicit traversal stack
+            Enter(NodeId), // Write the node's subtree
+            Separator, // ',' between siblings
+            Close(NodeId), // ')' plus the node's own label, length and comments
+        }
+
+        let mut stack = vec![Step::Enter(self.root)];
+
+        while let Some(step) = stack.pop() {
+            match step {
+                Step::Enter(id) => {
+                    let children = &self.nodes[id].children;
+                    if children.is_empty() { // Leaves are written in one go
+                        self.write_node_suffix(out, id, options)?;
+                        continue;
+                    }
+
+                    out.write_all(b"(")?;
+                    stack.push(Step::Close(id)); // Runs after all children have been written
+                    for (i, &child) in children.iter().enumerate().rev() { // Reverse so the first child is popped first
+                        stack.push(Step::Enter(child));
+                        if i > 0 {
+                            stack.push(Step::Separator);
+                        }
+                    }
+                }
+                Step::Separator => out.write_all(b",")?,
+                Step::Close(id) => {
+                    out.write_all(b")")?;
+                    self.write_node_suffix(out, id, options)?;
+                }
+            }
+        }
+
+        writeln!(out, ";")
+    }
+
+    // Writes what follows a node in Newick: its label, ':length' and [comments].
+    fn write_node_suffix<W: Write>(&self, out: &mut W, id: NodeId, options: &NewickOptions) -> io::Result<()> {
+        let node = &self.nodes[id];
+
+        if let Some(label) = &node.label
+            && (node.children.is_empty() || options.internal_labels) { // Leaf labels are always written
+            write_label(out, label)?;
+        } else if let Some(support) = node.support
+            && options.internal_labels { // Support read from a numeric label goes back in the label position
+            match self.support_scale {
+                Some(SupportScale::Percent) => write_percent(out, support)?, // On the scale it was read on
+                _ => write!(out, "{}", support)?,
+            }
+        }
+
+        if options.branch_lengths
+            && let Some(length) = node.length_to_parent {
+            match options.precision {
+                Some(digits) => write!(out, ":{:.*}", digits, length)?,
+                None => write!(out, ":{}", length)?, // f64 Display is the shortest string that parses back exactly
+            }
+        }
+
+        if options.comments {
+            for comment in &node.comments {
+                write!(out, "[{}]", comment)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Writes a label, quoting it with single quotes (and doubling inner quotes) if it would not survive unquoted.
+fn write_label<W: Write>(out: &mut W, label: &str) -> io::Result<()> {
+    let needs_quotes = label.is_empty() || label.chars().any(|c| c.is_whitespace() || matches!(c, '(' | ')' | '[' | ']' | ',' | ':' | ';' | '\'' | '"'));
+
+    if needs_quotes {
+        write!(out, "'{}'", label.replace('\'', "''"))
+    } else {
+        out.write_all(label.as_bytes())
+    }
+}
+
+// Writes a support proportion as a percentage with the fewest decimals that read back (divided by 100)
+// to exactly the same value, so ")95" is written as ")95" rather than ")95.00000000000001".
+fn write_percent<W: Write>(out: &mut W, support: f64) -> io::Result<()> {
+    let percent = support * 100.0;
+    for digits in 0..=17 {
+        let text = format!("{:.*}", digits, percent);
+        if text.parse::<f64>().map(|v| v / 100.0) == Ok(support) {
+            return out.write_all(text.as_bytes());
+        }
+    }
+    write!(out, "{}", percent) // Not reachable for finite values; kept as a plain fallback
+}
@@ -0,0 +1,27 @@
+// Helpers shared by the integration test files.
+use filigineacht_rs::tree::splits::Bitset;
+use filigineacht_rs::tree::structure::Tree;
+
+// Classifies every quartet directly from the splits of the induced four-taxon subtrees.
+pub fn brute_force_quartets(t1: &Tree, t2: &Tree, n: usize) -> (u64, u64, u64, u64, u64) {
+    let (mut same, mut diff, mut u1, mut u2, mut both) = (0, 0, 0, 0, 0);
+    for a in 0..n {
+        for b in a + 1..n {
+            for c in b + 1..n {
+                for d in c + 1..n {
+                    let mask = Bitset::from_ids(n, [a, b, c, d]);
+                    let s1 = t1.restricted_splits(n, &mask, false);
+                    let s2 = t2.restricted_splits(n, &mask, false);
+                    match (s1.first(), s2.first()) {
+                        (Some(x), Some(y)) if x == y => same += 1,
+                        (Some(_), Some(_)) => diff += 1,
+                        (None, Some(_)) => u1 += 1,
+                        (Some(_), None) => u2 += 1,
+                        (None, None) => both += 1,
+                    }
+                }
+            }
+        }
+    }
+    (same, diff, u1, u2, both)
+}
@@ -1,9 +1,11 @@
-use filigineacht_rs::*;
+use filigineacht_rs::tree::parser::parse_nwk;
 
 #[test]
 fn end_to_end_pipeline() {
     // 1. Load tree
+    let tree = parse_nwk("((A,B),(C,D));").unwrap();
+    assert_eq!(tree.taxa().len(), 4);
     // 2. Extract quartets
     // 3. Resolve network
     // 4. Assert something
-}
\ No newline at end of file
+}
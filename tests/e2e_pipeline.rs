@@ -0,0 +1,57 @@
+use filigineacht_rs::rooting::mad_root_newick;
+use filigineacht_rs::tree::canonical::unique_topologies;
+use filigineacht_rs::tree::consensus::majority_consensus;
+use filigineacht_rs::tree::construct::neighbor_joining;
+use filigineacht_rs::tree::distance::average_patristic_matrix;
+use filigineacht_rs::tree::parser::NewickReader;
+use filigineacht_rs::tree::random::{TaxonNaming, TreeGenerator};
+use filigineacht_rs::tree::structure::Tree;
+use filigineacht_rs::tree::taxa::TaxonSet;
+use filigineacht_rs::tree::writer::NewickOptions;
+
+#[test]
+fn simulated_gene_trees_through_the_tree_pipeline() {
+    // 1. Simulate a species tree and gene trees that each differ from it by at most one NNI.
+    let mut generator = TreeGenerator::new(2024).with_naming(TaxonNaming::Letters);
+    let species = generator.yule(12, 1.0).unwrap();
+    let internal: Vec<usize> = (0..species.nodes.len())
+        .filter(|&id| !species.nni_moves(id).is_empty())
+        .collect();
+    let mut file = Vec::new();
+    for i in 0..20 {
+        let mut gene = species.clone();
+        if i % 3 == 0 {
+            let node = internal[generator.rng().below(internal.len())];
+            let (child, sibling) = gene.nni_moves(node)[0];
+            gene.nni(child, sibling).unwrap();
+        }
+        gene.write_newick(&mut file, &NewickOptions::default()).unwrap();
+    }
+
+    // 2. Read the file back against a shared taxon set.
+    let mut reader = NewickReader::new(file.as_slice()).with_taxa(TaxonSet::new());
+    let genes: Vec<Tree> = reader.by_ref().collect::<Result<_, _>>().unwrap();
+    let taxa = reader.into_taxa().unwrap();
+    assert_eq!((genes.len(), taxa.len()), (20, 12));
+
+    // 3. The species topology is the most frequent one and the majority-rule consensus.
+    let mut species = species;
+    let mut species_taxa = taxa.clone();
+    species.bind_taxa(&mut species_taxa);
+    let classes = unique_topologies(&genes, false);
+    assert_eq!(classes[0].hash, species.unrooted_topology_hash());
+    let consensus = majority_consensus(&genes, &taxa, 0.5).unwrap();
+    assert_eq!(consensus.robinson_foulds(&species, taxa.len()).unwrap().distance, 0);
+
+    // 4. Neighbor joining on the averaged distances keeps the majority splits.
+    let average = average_patristic_matrix(&genes, &taxa).unwrap();
+    let mut nj = neighbor_joining(&average).unwrap();
+    nj.bind_taxa(&mut species_taxa);
+    assert!(nj.robinson_foulds(&species, taxa.len()).unwrap().normalized <= 0.25);
+
+    // 5. MAD-root every gene tree of the file in one pass.
+    let mut rooted = Vec::new();
+    let ambiguities = mad_root_newick(file.as_slice(), &mut rooted).unwrap();
+    assert_eq!(ambiguities.len(), 20);
+    assert!(ambiguities.iter().all(|a| *a < 1.0));
+}
@@ -0,0 +1,81 @@
+use filigineacht_rs::tree::random::{TaxonNaming, TreeGenerator};
+use filigineacht_rs::tree::structure::Tree;
+use filigineacht_rs::tree::taxa::TaxonSet;
+
+mod common;
+use common::brute_force_quartets;
+
+// One tree from every generator, bound to a shared taxon set.
+fn one_of_each(seed: u64, n: usize) -> (Vec<Tree>, TaxonSet) {
+    let mut taxa = TaxonSet::new();
+    let mut generator = TreeGenerator::new(seed);
+    let mut trees = vec![
+        generator.yule(n, 1.0).unwrap(),
+        generator.birth_death(n, 1.0, 0.5).unwrap(),
+        generator.uniform(n).unwrap(),
+        generator.caterpillar(n).unwrap(),
+        generator.balanced(n).unwrap(),
+    ];
+    for tree in &mut trees {
+        tree.bind_taxa(&mut taxa);
+    }
+    (trees, taxa)
+}
+
+#[test]
+fn every_generator_resolves_every_quartet() {
+    for seed in 0..3 {
+        let (trees, taxa) = one_of_each(seed, 9);
+        assert_eq!(taxa.len(), 9); // All generators use the same default names
+        for tree in &trees {
+            let (same, _, _, _, _) = brute_force_quartets(tree, tree, taxa.len());
+            assert_eq!(same, 126); // Binary trees resolve all C(9, 4) quartets
+        }
+    }
+}
+
+#[test]
+fn naming_does_not_change_the_generated_shape() {
+    let n = 10;
+    let schemes = [TaxonNaming::Letters, TaxonNaming::Prefix("sp".to_string())];
+    let trees: Vec<Tree> = schemes.iter().map(|naming| {
+        let mut taxa = TaxonSet::from_names(naming.names(n).unwrap()); // Ids follow the order of the scheme
+        let mut generator = TreeGenerator::new(11).with_naming(naming.clone());
+        let mut tree = generator.yule(n, 1.0).unwrap();
+        tree.bind_taxa(&mut taxa);
+        tree
+    }).collect();
+
+    assert_eq!(brute_force_quartets(&trees[0], &trees[1], n), (210, 0, 0, 0, 0));
+    assert_ne!(trees[0].to_newick(), trees[1].to_newick());
+}
+
+#[test]
+fn random_generators_differ_between_seeds() {
+    let (first, taxa) = one_of_each(1, 10);
+    let mut generator = TreeGenerator::new(2);
+    for (mut tree, old) in [generator.yule(10, 1.0).unwrap(), generator.birth_death(10, 1.0, 0.5).unwrap(), generator.uniform(10).unwrap()]
+        .into_iter()
+        .zip(&first)
+    {
+        let mut shared = taxa.clone();
+        tree.bind_taxa(&mut shared);
+        let (same, different, _, _, _) = brute_force_quartets(old, &tree, taxa.len());
+        assert_eq!(same + different, 210);
+        assert!(different > 0);
+    }
+}
+
+#[test]
+fn caterpillar_and_balanced_trees_share_known_quartets() {
+    let (trees, taxa) = one_of_each(0, 8);
+    let (caterpillar, balanced) = (&trees[3], &trees[4]);
+    assert_eq!(caterpillar.splits(taxa.len()).len(), 5); // n - 3 nested splits
+    assert_eq!(balanced.splits(taxa.len()).len(), 5);
+
+    // The caterpillar resolves every quartet a < b < c < d as ab|cd (in name order). The balanced tree
+    // disagrees on the 16 quartets made of an inner cherry, (t3,t4) or (t5,t6), one leaf of the other
+    // cherry in its half and one leaf of the other half, e.g. t5t6|t1t7 against t1t5|t6t7.
+    assert_eq!(caterpillar.to_newick(), "(((((((t1,t2),t3),t4),t5),t6),t7),t8);");
+    assert_eq!(brute_force_quartets(caterpillar, balanced, taxa.len()), (54, 16, 0, 0, 0));
+}
@@ -0,0 +1,932 @@
+use filigineacht_rs::tree::structure::Tree;
+use filigineacht_rs::tree::compare::pairwise_robinson_foulds;
+use filigineacht_rs::tree::splits::{split_frequencies, Bitset};
+use filigineacht_rs::tree::taxa::TaxonSet;
+use filigineacht_rs::tree::structure::{MetaValue, SupportScale};
+use filigineacht_rs::tree::network::{HybridKind, HybridTag};
+use filigineacht_rs::tree::parser::{
+    parse_enwk, parse_nwk, parse_nwk_in, parse_nwk_with, NewickReader, NexusReader, ParseErrorKind, ParseOptions,
+};
+use filigineacht_rs::tree::writer::NewickOptions;
+use filigineacht_rs::tree::operations::TreeError;
+use filigineacht_rs::rooting::mad_root_newick;
+
+mod common;
+use common::brute_force_quartets;
+
+#[test]
+fn newick_reader_streams_trees_one_per_line() {
+    let input = "((A:1,B:2):1,C:3);\n(A,(B,C));\n\n((A,C),B);\n";
+    let trees: Vec<_> = NewickReader::new(input.as_bytes())
+        .collect::<Result<_, _>>()
+        .unwrap();
+
+    assert_eq!(trees.len(), 3);
+    assert_eq!(trees[0].taxa(), vec!["A", "B", "C"]);
+    assert_eq!(trees[2].taxa(), vec!["A", "C", "B"]);
+}
+
+#[test]
+fn newick_reader_handles_concatenated_trees_and_bad_records() {
+    let input = "(A,B);(A,B:x);(C,D)";
+    let results: Vec<_> = NewickReader::new(input.as_bytes()).collect();
+
+    assert_eq!(results.len(), 3);
+    assert!(results[0].is_ok());
+    assert!(results[1].is_err()); // Invalid branch length only fails its own tree
+    assert_eq!(results[2].as_ref().unwrap().taxa(), vec!["C", "D"]);
+}
+
+#[test]
+fn parse_single_tree_matches_reader() {
+    let text = "(A:0.1,(B:0.2,C:0.3)X:0.4)Root;";
+    let tree = parse_nwk(text).unwrap();
+    assert_eq!(tree.node_label(tree.root), Some("Root"));
+    assert_eq!(tree.leaves().len(), 3);
+
+    let read: Vec<Tree> = NewickReader::new(text.as_bytes()).collect::<Result<_, _>>().unwrap();
+    assert_eq!(read.len(), 1);
+    assert_eq!(read[0].to_newick(), tree.to_newick());
+}
+
+#[test]
+fn quoted_labels_and_escapes() {
+    let tree = parse_nwk("('Homo sapiens':0.1,'O''Brien''s (x)':0.2,\"Pan, troglodytes\");").unwrap();
+    assert_eq!(tree.taxa(), vec!["Homo sapiens", "O'Brien's (x)", "Pan, troglodytes"]);
+    assert_eq!(tree.nodes[1].length_to_parent, Some(0.1));
+}
+
+#[test]
+fn comments_are_kept_on_nodes() {
+    let tree = parse_nwk("[&R] ((A[&h=1]:0.1[note],B:0.2)[&&NHX:B=95]:0.3,C);").unwrap();
+    let a = tree.leaves()[0];
+    assert_eq!(tree.node_label(a), Some("A"));
+    assert_eq!(tree.nodes[a].length_to_parent, Some(0.1));
+    assert_eq!(tree.node_comments(a), ["&h=1", "note"]);
+    assert_eq!(tree.node_comments(tree.root), ["&R"]);
+    let clade = tree.parent(a).unwrap();
+    assert_eq!(tree.node_comments(clade), ["&&NHX:B=95"]);
+    assert_eq!(tree.nodes[clade].length_to_parent, Some(0.3));
+}
+
+#[test]
+fn newick_reader_ignores_semicolons_in_quotes_and_comments() {
+    let input = "('a;b',C)[x;y];\n(D,E);";
+    let trees: Vec<_> = NewickReader::new(input.as_bytes())
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(trees.len(), 2);
+    assert_eq!(trees[0].taxa(), vec!["a;b", "C"]);
+}
+
+#[test]
+fn parse_errors_report_position_and_snippet() {
+    let err = parse_nwk("((A:0.1,B:0.2x),\n C:abc);").unwrap_err();
+    assert!(matches!(err.kind, ParseErrorKind::InvalidBranchLength));
+    assert_eq!((err.position.line, err.position.column, err.position.offset), (1, 11, 10));
+    assert_eq!(err.snippet, "0.2x");
+    assert_eq!(err.tree_index, None);
+
+    let err = parse_nwk("((A,B),C;").unwrap_err();
+    assert!(matches!(err.kind, ParseErrorKind::UnbalancedParentheses));
+    assert_eq!(err.position.column, 1);
+}
+
+#[test]
+fn reader_errors_are_relative_to_the_file() {
+    let input = "(A,B);\n(C,D);\n(E,\n  F:x);\n";
+    let results: Vec<_> = NewickReader::new(input.as_bytes()).collect();
+    let err = results[2].as_ref().unwrap_err();
+
+    assert_eq!(err.tree_index, Some(2));
+    assert_eq!((err.position.line, err.position.column), (4, 5));
+    assert_eq!(&input[err.position.offset..err.position.offset + 1], "x");
+    assert_eq!(err.to_string(), "tree 3, line 4, column 5 (byte 22): invalid branch length near `x`");
+}
+
+#[test]
+fn newick_round_trip() {
+    let input = "((A:0.1,'Homo sapiens':1e-7)'clade ''x''':0.3[&&NHX:B=95],(C:2,D:0.333333333333)95:1.5)Root;";
+    let tree = parse_nwk(input).unwrap();
+    let written = tree.to_newick();
+    let reparsed = parse_nwk(&written).unwrap();
+
+    assert_eq!(reparsed.to_newick(), written);
+    assert_eq!(reparsed.taxa(), tree.taxa());
+    for (a, b) in tree.preorder().into_iter().zip(reparsed.preorder()) {
+        assert_eq!(tree.node_label(a), reparsed.node_label(b));
+        assert_eq!(tree.nodes[a].length_to_parent, reparsed.nodes[b].length_to_parent);
+        assert_eq!(tree.children(a).len(), reparsed.children(b).len());
+    }
+}
+
+#[test]
+fn unlabeled_leaves_round_trip() {
+    for input in ["(,B);", "(:0.2,B);", "((,):1,(A,)X);", "('',B);"] {
+        let tree = parse_nwk(input).unwrap();
+        let written = tree.to_newick();
+        assert_eq!(written, input);
+
+        let reparsed = parse_nwk(&written).unwrap();
+        assert_eq!(reparsed.nodes.len(), tree.nodes.len());
+        assert_eq!(reparsed.leaves().len(), tree.leaves().len());
+    }
+
+    let tree = parse_nwk("(:0.2,B);").unwrap();
+    let empty = tree.children(tree.root)[0];
+    assert_eq!(tree.node_label(empty), None);
+    assert_eq!(tree.nodes[empty].length_to_parent, Some(0.2));
+}
+
+#[test]
+fn newick_writer_options() {
+    let tree = parse_nwk("((A:0.123456,B:1)X:0.5,C:2)R;").unwrap();
+    let options = NewickOptions { precision: Some(2), internal_labels: false, ..NewickOptions::default() };
+    assert_eq!(tree.to_newick_with(&options), "((A:0.12,B:1.00):0.50,C:2.00);");
+    assert_eq!(tree.to_newick_with(&NewickOptions::topology_only()), "((A,B),C);");
+
+    let mut out = Vec::new();
+    tree.write_newick(&mut out, &NewickOptions::topology_only()).unwrap();
+    assert_eq!(out, b"((A,B),C);\n");
+}
+
+#[test]
+fn enewick_network_with_shared_hybrid() {
+    let net = parse_enwk("((A:1,(B:1)#H1:0.5::0.3)X:1,(#H1:0.2::0.7,C:1)Y:1)R;").unwrap();
+
+    let hybrids = net.hybrid_nodes();
+    assert_eq!(hybrids.len(), 1);
+    assert_eq!(net.reticulation_count(), 1);
+    let h = hybrids[0];
+    assert_eq!(net.nodes[h].hybrid, Some(HybridTag { kind: HybridKind::Hybridization, index: 1 }));
+    assert_eq!(net.children(h).len(), 1);
+
+    let parents = net.parents(h);
+    let labels: Vec<_> = parents.iter().map(|&p| net.node_label(p).unwrap()).collect();
+    assert_eq!(labels, vec!["X", "Y"]);
+    assert_eq!(net.inheritance(parents[0], h), Some(0.3));
+    assert_eq!(net.edge_between(parents[1], h).unwrap().length, Some(0.2));
+
+    let mut taxa = net.taxa();
+    taxa.sort();
+    assert_eq!(taxa, vec!["A", "B", "C"]);
+}
+
+#[test]
+fn enewick_fills_missing_gamma_and_rejects_bad_networks() {
+    let net = parse_enwk("((A,#LGT1:::0.9),(C)#LGT1);").unwrap();
+    let h = net.hybrid_nodes()[0];
+    let gammas: Vec<_> = net.nodes[h].parents.iter().map(|&e| net.edges[e].gamma.unwrap()).collect();
+    assert!((gammas[0] - 0.9).abs() < 1e-12 && (gammas[1] - 0.1).abs() < 1e-12);
+
+    assert!(parse_enwk("((A)#H1,(B)#H1);").is_err()); // Children given twice
+    assert!(parse_enwk("((A,#Hx),B);").is_err()); // Malformed tag
+    assert!(parse_enwk("((A,sample#3),C);").is_err()); // A tag needs a kind before its index
+    assert_eq!(HybridTag::parse("3"), None);
+    assert_eq!(HybridTag::parse("H-1"), None);
+
+    let quoted = parse_enwk("((A,'sample#3')'clade#H1',C);").unwrap();
+    assert!(quoted.hybrid_nodes().is_empty()); // Quoted labels keep their '#'
+    assert_eq!(quoted.taxa(), vec!["A", "sample#3", "C"]);
+    assert!(quoted.nodes.iter().any(|n| n.label.as_deref() == Some("clade#H1") && n.hybrid.is_none()));
+    let err = parse_enwk("((A,#H1)#H1,B);").unwrap_err(); // Cycle
+    assert!(matches!(err.kind, ParseErrorKind::InvalidNetwork(_)));
+    assert_eq!((err.position.offset, err.snippet.as_str()), (4, "#H1")); // The reference inside its own subtree
+}
+
+#[test]
+fn nexus_trees_block_with_translate() {
+    let input = "#NEXUS\n[written by MrBayes]\nBEGIN TAXA;\n  DIMENSIONS NTAX=3;\nEND;\n\
+                 BEGIN TREES;\n  TRANSLATE\n    1 Homo_sapiens,\n    2 'Pan troglodytes',\n    3 Gorilla;\n\
+                 TREE STATE_0 = [&R] ((1:0.1,2:0.2):0.05,3:0.3);\n\
+                 TREE * STATE_1 = ((1,3),2);\nEND;\n";
+    let mut reader = NexusReader::new(input.as_bytes());
+
+    let first = reader.next().unwrap().unwrap();
+    assert_eq!(reader.tree_name(), Some("STATE_0"));
+    assert_eq!(first.taxa(), vec!["Homo_sapiens", "Pan troglodytes", "Gorilla"]);
+    assert_eq!(first.node_comments(first.root), ["&R"]);
+
+    let second = reader.next().unwrap().unwrap();
+    assert_eq!(reader.tree_name(), Some("STATE_1"));
+    assert_eq!(second.taxa(), vec!["Homo_sapiens", "Gorilla", "Pan troglodytes"]);
+    assert!(reader.next().is_none());
+}
+
+#[test]
+fn nexus_errors_point_into_the_file() {
+    let input = "#NEXUS\nbegin trees;\ntree t1 = (A,B);\ntree t2 = (A,\n B:x);\nend;";
+    let results: Vec<_> = NexusReader::new(input.as_bytes()).collect();
+    assert_eq!(results.len(), 2);
+    let err = results[1].as_ref().unwrap_err();
+    assert_eq!(err.tree_index, Some(1));
+    assert_eq!((err.position.line, err.position.column), (5, 4));
+
+    let not_nexus: Vec<_> = NexusReader::new("(A,B);".as_bytes()).collect();
+    assert!(matches!(not_nexus[0].as_ref().unwrap_err().kind, ParseErrorKind::InvalidNexus(_)));
+}
+
+#[test]
+fn nhx_and_beast_annotations_become_typed_metadata() {
+    let tree = parse_nwk(
+        "((A[&&NHX:S=human:B=95:D=N],B[&posterior=0.98,height=1.2,height_95%_HPD={1.0,1.5},name=\"x y\"])[&&NHX:B=80]:0.1,C);",
+    )
+    .unwrap();
+    let leaves = tree.leaves();
+    let (a, b) = (leaves[0], leaves[1]);
+
+    assert_eq!(tree.metadata(a, "S"), Some(&MetaValue::Str("human".to_string())));
+    assert_eq!(tree.metadata(a, "B"), Some(&MetaValue::Int(95)));
+    assert_eq!(tree.metadata(b, "posterior").and_then(MetaValue::as_f64), Some(0.98));
+    assert_eq!(
+        tree.metadata(b, "height_95%_HPD"),
+        Some(&MetaValue::List(vec![MetaValue::Float(1.0), MetaValue::Float(1.5)]))
+    );
+    assert_eq!(tree.metadata(b, "name").and_then(MetaValue::as_str), Some("x y"));
+    assert_eq!(tree.metadata(tree.parent(a).unwrap(), "B").and_then(MetaValue::as_f64), Some(80.0));
+    assert_eq!(tree.node_comments(a).len(), 1); // The raw comment is still kept
+}
+
+#[test]
+fn numeric_internal_labels_become_support() {
+    let options = ParseOptions { support: Some(SupportScale::Auto), ..Default::default() };
+    let tree = parse_nwk_with("((A,B)95:0.1,(C,D)clade:0.2,(E,F)100)root;", &options).unwrap();
+    let ab = tree.parent(tree.leaves()[0]).unwrap();
+    let cd = tree.parent(tree.leaves()[2]).unwrap();
+
+    assert_eq!(tree.support(ab), Some(0.95));
+    assert_eq!(tree.node_label(ab), None);
+    assert_eq!(tree.node_label(cd), Some("clade")); // Names are not support
+    assert_eq!(tree.node_label(tree.root), Some("root"));
+    assert_eq!(tree.support_scale, Some(SupportScale::Percent));
+    assert_eq!(tree.to_newick(), "((A,B)95:0.1,(C,D)clade:0.2,(E,F)100)root;"); // Written back as percentages
+
+    let bootstrap = parse_nwk_with("((A,B)57,(C,D)33.3,E);", &options).unwrap();
+    assert_eq!(bootstrap.to_newick(), "((A,B)57,(C,D)33.3,E);");
+
+    let mut posterior = parse_nwk("((A,B)0.87:0.02,C);").unwrap();
+    assert_eq!(posterior.labels_to_support(SupportScale::Auto), 1);
+    assert_eq!(posterior.support(posterior.parent(posterior.leaves()[0]).unwrap()), Some(0.87));
+    assert_eq!(posterior.to_newick(), "((A,B)0.87:0.02,C);");
+}
+
+#[test]
+fn taxon_set_is_shared_across_trees() {
+    let mut taxa = TaxonSet::new();
+    let t1 = parse_nwk_in("((A,B),(C,D));", &mut taxa).unwrap();
+    let t2 = parse_nwk_in("((D,E),(B,A));", &mut taxa).unwrap();
+
+    assert_eq!(taxa.len(), 5);
+    assert_eq!(taxa.id("E"), Some(4));
+    assert_eq!(taxa.name(3), Some("D"));
+    assert_eq!(t1.taxon_ids(), vec![0, 1, 2, 3]);
+    assert_eq!(t2.taxon_ids(), vec![3, 4, 1, 0]);
+
+    let leaves = t2.leaves_by_taxon(taxa.len());
+    assert_eq!(leaves[2], None); // C is missing from the second tree
+    assert_eq!(t2.node_label(leaves[4].unwrap()), Some("E"));
+    assert_eq!(t2.taxon(t2.root), None);
+}
+
+#[test]
+fn readers_bind_trees_to_a_taxon_set() {
+    let mut reader = NewickReader::new("(A,B,C);\n(C,(B,D));".as_bytes()).with_taxa(TaxonSet::new());
+    let trees: Vec<_> = reader.by_ref().collect::<Result<_, _>>().unwrap();
+    let taxa = reader.into_taxa().unwrap();
+
+    assert_eq!(taxa.names(), ["A", "B", "C", "D"]);
+    assert_eq!(trees[1].taxon_ids(), vec![2, 1, 3]);
+}
+
+#[test]
+fn splits_are_canonical_bitsets_with_edge_data() {
+    let mut taxa = TaxonSet::new();
+    let mut rooted = parse_nwk_in("(((A,B)0.9:0.1,C):0.2,(D,E):0.3);", &mut taxa).unwrap();
+    rooted.labels_to_support(SupportScale::Auto);
+    let other = parse_nwk_in("((E,D),(C,(B,A)));", &mut taxa).unwrap();
+    let n = taxa.len();
+
+    let splits = rooted.splits(n);
+    assert_eq!(splits.len(), 2); // AB|CDE and ABC|DE (the root edges merge into one split)
+    assert_eq!(splits, other.splits(n)); // Same topology, different child order and rooting
+
+    let de = splits.iter().find(|s| s.bits == Bitset::from_ids(n, [3, 4])).unwrap();
+    assert!((de.length.unwrap() - 0.5).abs() < 1e-12);
+    let ab = splits.iter().find(|s| s.size() == 3).unwrap(); // Stored as the side without taxon 0 (A)
+    assert_eq!(ab.bits, Bitset::from_ids(n, [2, 3, 4]));
+    assert_eq!(ab.support, Some(0.9));
+
+    let freq = split_frequencies([&rooted, &other], n);
+    assert!(freq.values().all(|&c| c == 2));
+}
+
+#[test]
+fn splits_ignore_taxa_added_after_the_width_was_taken() {
+    let mut taxa = TaxonSet::new();
+    let first = parse_nwk_in("((A,B),(C,D));", &mut taxa).unwrap();
+    let n = taxa.len();
+    let grown = parse_nwk_in("(((A,B),X),(C,D));", &mut taxa).unwrap(); // X gets id 4, outside width n
+
+    assert_eq!(grown.taxon_bitset(n), Bitset::from_ids(n, [0, 1, 2, 3]));
+    assert_eq!(grown.clade_bitsets(n)[grown.root].count(), 4);
+    assert_eq!(grown.splits(n), first.splits(n));
+    assert_eq!(grown.leaves_by_taxon(n).iter().flatten().count(), 4);
+
+    let mut bits = Bitset::new(n);
+    bits.insert(n + 10); // Out-of-range ids are ignored rather than panicking
+    assert!(bits.is_empty());
+}
+
+#[test]
+fn robinson_foulds_distances() {
+    let mut taxa = TaxonSet::new();
+    let t1 = parse_nwk_in("(((A,B),C),(D,(E,F)));", &mut taxa).unwrap();
+    let t2 = parse_nwk_in("(((A,C),B),(D,(E,F)));", &mut taxa).unwrap();
+    let same = parse_nwk_in("((F,E),(D,(C,(B,A))));", &mut taxa).unwrap();
+    let n = taxa.len();
+
+    assert_eq!(t1.robinson_foulds(&same, n).unwrap().distance, 0);
+    let rf = t1.robinson_foulds(&t2, n).unwrap();
+    assert_eq!((rf.distance, rf.max, rf.shared_taxa), (2, 6, 6));
+    assert!((rf.normalized - 1.0 / 3.0).abs() < 1e-12);
+
+    // Only the shared taxa A, B, C, E, F are compared; the extra taxon X is ignored.
+    let t3 = parse_nwk_in("((((A,B),X),C),(E,F));", &mut taxa).unwrap();
+    let rf = t1.robinson_foulds(&t3, taxa.len()).unwrap();
+    assert_eq!((rf.distance, rf.shared_taxa), (0, 5));
+
+    let matrix = pairwise_robinson_foulds(&[t1, t2, same], n).unwrap();
+    assert_eq!(matrix[0][1].distance, 2);
+    assert_eq!(matrix[1][2].distance, matrix[2][1].distance);
+}
+
+#[test]
+fn weighted_robinson_foulds_uses_branch_lengths() {
+    let mut taxa = TaxonSet::new();
+    let t1 = parse_nwk_in("((A:1,B:1):1,(C:1,D:1):1);", &mut taxa).unwrap();
+    let t2 = parse_nwk_in("((A:1,B:2):0.5,(C:1,D:1):0.5);", &mut taxa).unwrap();
+    let t3 = parse_nwk_in("((A:1,C:1):2,(B:1,D:1));", &mut taxa).unwrap();
+    let n = taxa.len();
+
+    assert!((t1.weighted_robinson_foulds(&t1, n).unwrap()).abs() < 1e-12);
+    assert!((t1.weighted_robinson_foulds(&t2, n).unwrap() - 2.0).abs() < 1e-12); // |1-2| on B, |2-1| on AB|CD
+    assert!((t1.weighted_robinson_foulds(&t3, n).unwrap() - 4.0).abs() < 1e-12); // AB|CD (2) and AC|BD (2) unmatched
+}
+
+#[test]
+fn comparisons_reject_unbound_trees() {
+    let mut taxa = TaxonSet::new();
+    let bound = parse_nwk_in("((A,B),(C,D));", &mut taxa).unwrap();
+    let unbound = parse_nwk("((A,C),(B,D));").unwrap(); // Would otherwise have no taxa and compare as identical
+    let unlabeled = parse_nwk_in("((A,B),(C,));", &mut taxa).unwrap();
+    let n = taxa.len();
+
+    assert_eq!(unbound.robinson_foulds(&unbound, n), Err(TreeError::UnboundLeaf(2)));
+    assert_eq!(bound.robinson_foulds(&unbound, n), Err(TreeError::UnboundLeaf(2)));
+    assert_eq!(bound.weighted_robinson_foulds(&unlabeled, n), Err(TreeError::UnboundLeaf(6)));
+    assert_eq!(unlabeled.quartet_distance(&bound, n), Err(TreeError::UnboundLeaf(6)));
+    assert!(pairwise_robinson_foulds(&[bound.clone(), unbound], n).is_err());
+    assert_eq!(bound.quartet_distance(&bound, n).unwrap().distance(), 0);
+}
+
+#[test]
+fn quartet_distance_matches_brute_force() {
+    let mut taxa = TaxonSet::new();
+    let t1 = parse_nwk_in("((((A,B),C),(D,E)),((F,G),(H,I)));", &mut taxa).unwrap();
+    let t2 = parse_nwk_in("(((A,C,B),D),(E,(F,H)),(G,I));", &mut taxa).unwrap();
+    let t3 = parse_nwk_in("(A,B,C,D,E,(F,G,H,I));", &mut taxa).unwrap();
+    let n = taxa.len();
+
+    for (x, y) in [(&t1, &t2), (&t2, &t3), (&t3, &t1), (&t1, &t1)] {
+        let q = x.quartet_distance(y, n).unwrap();
+        assert_eq!(q.total, 126);
+        assert_eq!(
+            (q.same, q.different, q.unresolved_in_first, q.unresolved_in_second, q.unresolved_in_both),
+            brute_force_quartets(x, y, n)
+        );
+    }
+    assert_eq!(t1.quartet_distance(&t1, n).unwrap().distance(), 0);
+}
+
+#[test]
+fn rerooting_keeps_splits_lengths_and_support() {
+    let mut taxa = TaxonSet::new();
+    let mut tree = parse_nwk_in("(((A:1,B:1)0.7:1,C:1)0.9:2,(D:1,E:1):3);", &mut taxa).unwrap();
+    tree.labels_to_support(SupportScale::Auto);
+    let n = taxa.len();
+    let before = tree.splits(n);
+    let total_length: f64 = tree.nodes.iter().filter_map(|x| x.length_to_parent).sum();
+
+    tree.root_with_outgroup(&["D", "E"]).unwrap();
+    assert_eq!(tree.nodes.len(), 9); // Old bifurcating root was suppressed
+    tree.root_with_outgroup(&["A"]).unwrap();
+    let root_children: Vec<_> = tree.children(tree.root).iter().map(|&c| tree.node_label(c)).collect();
+    assert!(root_children.contains(&Some("A")));
+
+    let after = tree.splits(n);
+    assert_eq!(after, before);
+    for (x, y) in before.iter().zip(&after) {
+        assert_eq!(x.support, y.support);
+        assert!((x.length.unwrap() - y.length.unwrap()).abs() < 1e-12);
+    }
+    let new_total: f64 = tree.nodes.iter().filter_map(|x| x.length_to_parent).sum();
+    assert!((new_total - total_length).abs() < 1e-12);
+    assert!(tree.parent(tree.root).is_none());
+    assert!(tree.root_with_outgroup(&["A", "D"]).is_err()); // Not a clade
+    assert!(tree.root_with_outgroup(&["Z"]).is_err());
+}
+
+#[test]
+fn reroot_at_node_edge_and_midpoint() {
+    let mut tree = parse_nwk("((A:1,B:4):1,(C:1,D:1):1);").unwrap();
+    let b = tree.leaves()[1];
+    tree.reroot_on_edge(b, 0.25).unwrap();
+    let b = tree.leaves().into_iter().find(|&l| tree.node_label(l) == Some("B")).unwrap();
+    assert_eq!(tree.parent(b), Some(tree.root));
+    assert_eq!(tree.nodes[b].length_to_parent, Some(1.0));
+
+    tree.reroot_at_midpoint().unwrap(); // Longest path B–C/D has length 7; midpoint is 3.5 from B
+    let b = tree.leaves().into_iter().find(|&l| tree.node_label(l) == Some("B")).unwrap();
+    assert!((tree.patristic_distance(tree.root, b).unwrap() - 3.5).abs() < 1e-12);
+
+    let inner = tree.parent(tree.leaves()[0]).unwrap();
+    tree.reroot_at_node(inner).unwrap();
+    assert!(tree.nodes[tree.root].length_to_parent.is_none());
+    assert_eq!(tree.leaves().len(), 4);
+}
+
+#[test]
+fn rerooting_at_a_leaf_keeps_it_a_leaf() {
+    let mut taxa = TaxonSet::new();
+    let mut tree = parse_nwk_in("((A:1,B:2):1,(C:1,D:1):1);", &mut taxa).unwrap();
+    let reference = tree.clone();
+    let n = taxa.len();
+
+    tree.reroot_at_node(tree.leaves()[0]).unwrap(); // Leaf A
+    let mut names = tree.taxa();
+    names.sort();
+    assert_eq!(names, vec!["A", "B", "C", "D"]);
+    assert_eq!(tree.splits(n), reference.splits(n));
+    assert_eq!(tree.robinson_foulds(&reference, n).unwrap().shared_taxa, 4);
+
+    let a = tree.leaves().into_iter().find(|&l| tree.node_label(l) == Some("A")).unwrap();
+    assert_eq!(tree.parent(a), Some(tree.root)); // Rooted on A's pendant edge, next to A
+    assert_eq!(tree.nodes[a].length_to_parent, Some(0.0));
+    assert!(tree.is_valid());
+}
+
+#[test]
+fn mad_and_min_var_recover_a_clock_root() {
+    // Unrooted form of the ultrametric tree (((A:1,B:1):1,C:2):1,D:3); the root is 3 from D.
+    let tree = parse_nwk("((A:1,B:1):1,C:2,D:4);").unwrap();
+    for rooted in [tree.mad_root().unwrap(), tree.min_var_root().unwrap()] {
+        let t = &rooted.tree;
+        for leaf in t.leaves() {
+            assert!((t.patristic_distance(t.root, leaf).unwrap() - 3.0).abs() < 1e-9);
+        }
+        assert!(rooted.score < 1e-9);
+        assert!(rooted.ambiguity < 1e-9);
+    }
+
+    // A bifurcating root is suppressed first, so its two edges are scored as one.
+    let rooted = parse_nwk("((A:1,B:1):1,(C:1,D:1):1);").unwrap().mad_root().unwrap();
+    assert!(rooted.score < 1e-9);
+    assert_eq!(rooted.tree.children(rooted.tree.root).len(), 2);
+
+    assert!(parse_nwk("(A:1,B:1);").unwrap().mad_root().is_err());
+    assert!(parse_nwk("(A,B,C);").unwrap().mad_root().is_err());
+}
+
+#[test]
+fn mad_batch_roots_a_gene_tree_file() {
+    let input = "((A:1,B:1):1,C:2,D:4);\n(A:1,B:1,C:1,D:1);\n";
+    let mut out = Vec::new();
+    let ambiguities = mad_root_newick(input.as_bytes(), &mut out).unwrap();
+
+    assert_eq!(ambiguities.len(), 2);
+    assert!(ambiguities[0] < 0.5);
+    assert!((ambiguities[1] - 1.0).abs() < 1e-9); // A star tree has no preferred root
+    let mut reader = NewickReader::new(out.as_slice()).with_taxa(TaxonSet::from_names(["A", "B", "C", "D"]));
+    let rooted: Vec<_> = reader.by_ref().collect::<Result<_, _>>().unwrap();
+    let mut taxa = reader.into_taxa().unwrap();
+    let reference = parse_nwk_in("(((A,B),C),D);", &mut taxa).unwrap();
+    assert_eq!(rooted.len(), 2);
+    assert_eq!(taxa.len(), 4);
+    assert_eq!(rooted[0].splits(4).len(), 1); // AB|CD, so the comparison below is not vacuous
+    assert_eq!(rooted[0].robinson_foulds(&reference, 4).unwrap().distance, 0);
+    assert_eq!(rooted[1].robinson_foulds(&reference, 4).unwrap().distance, 1); // The star tree lacks AB|CD
+    let root_children: Vec<_> = rooted[0].children(rooted[0].root).iter().map(|&c| rooted[0].node_label(c)).collect();
+    assert!(root_children.contains(&Some("D"))); // Rooted on the long edge to D
+
+    assert!(mad_root_newick("(A:1,B:1);\n".as_bytes(), &mut Vec::new()).is_err());
+}
+
+#[test]
+fn restrict_and_prune_suppress_unary_nodes() {
+    let tree = parse_nwk("(((A:1,B:2)90:1,C:3)80:2,(D:1,E:1):1);").unwrap();
+
+    let quartet = tree.restrict_to(&["A", "C", "D", "E"]).unwrap();
+    assert_eq!(quartet.to_newick(), "((A:2,C:3)80:2,(D:1,E:1):1);");
+    assert_eq!(quartet.nodes.len(), 7);
+
+    let pruned = tree.prune(&["D", "E", "Z"]).unwrap(); // Z is not in the tree and is ignored
+    assert_eq!(pruned.to_newick(), "((A:1,B:2)90:1,C:3)80;");
+    assert_eq!(pruned.prune(&["A", "B", "C"]).err(), Some(TreeError::EmptyTaxonSet));
+
+    let single = tree.restrict_to(&["B"]).unwrap();
+    assert_eq!(single.to_newick(), "B;");
+    assert!(tree.restrict_to(&["A", "Z"]).is_err());
+    assert_eq!(tree.leaves().len(), 5); // The source tree is left untouched
+}
+
+#[test]
+fn collapsing_weak_edges_leaves_unresolved_quartets() {
+    let mut taxa = TaxonSet::new();
+    let mut tree = parse_nwk_with("(((A:1,B:1)0.95:1,C:1)0.4:0.5,(D:1,E:1)0.2:0.001);", &ParseOptions::default()).unwrap();
+    tree.labels_to_support(SupportScale::Proportion);
+    let mut binary = tree.clone();
+
+    assert_eq!(tree.collapse_below_support(0.5), 2);
+    assert_eq!(tree.to_newick(), "((A:1,B:1)0.95:1.5,C:1.5,D:1.001,E:1.001);");
+    let (a, e) = (tree.leaves()[0], tree.leaves()[4]);
+    assert!((tree.patristic_distance(a, e).unwrap() - 3.501).abs() < 1e-9);
+
+    tree.bind_taxa(&mut taxa);
+    binary.bind_taxa(&mut taxa);
+    let q = tree.quartet_distance(&binary, taxa.len()).unwrap();
+    assert_eq!(q.unresolved_in_first, 2); // Only quartets containing both A and B stay resolved
+    assert_eq!(q.different, 0);
+
+    let mut short = binary.clone();
+    assert_eq!(short.collapse_below_length(0.01), 1);
+    assert_eq!(short.to_newick_with(&NewickOptions::topology_only()), "(((A,B),C),D,E);");
+    assert_eq!(short.collapse_below_length(0.01), 0);
+}
+
+#[test]
+fn lca_index_matches_parent_walks() {
+    let mut tree = parse_nwk("((A:1,(B:2,C:0.5):1):2,(D:1,(E:1,(F:3,G:1):0.5):2):1,H:4);").unwrap();
+    let plain = tree.clone();
+    tree.build_lca_index();
+    assert!(tree.lca_index().is_some());
+
+    let n = tree.nodes.len();
+    for a in 0..n {
+        for b in 0..n {
+            assert_eq!(tree.lca(a, b), plain.lca(a, b));
+            assert_eq!(tree.topological_distance(a, b), plain.topological_distance(a, b));
+            assert!((tree.patristic_distance(a, b).unwrap() - plain.patristic_distance(a, b).unwrap()).abs() < 1e-9);
+        }
+    }
+
+    // Changing the tree drops the index, so answers follow the new topology.
+    let g = tree.leaves().into_iter().find(|&l| tree.node_label(l) == Some("G")).unwrap();
+    tree.reroot_on_edge(g, 0.5).unwrap();
+    assert!(tree.lca_index().is_none());
+
+    // A stale index left by direct edits is ignored once the node count or root differ.
+    tree.build_lca_index();
+    let mut grown = tree.clone();
+    grown.nodes.push(Default::default());
+    assert!(grown.lca_index().is_none());
+    let mut rerooted = tree.clone();
+    rerooted.root = tree.children(tree.root)[0];
+    assert!(rerooted.lca_index().is_none());
+    assert!(tree.lca_index().is_some());
+
+    // Trees built by hand start without an index.
+    let by_hand = Tree { nodes: plain.nodes.clone(), root: plain.root, lca_index: None, support_scale: None };
+    assert_eq!(by_hand.lca(1, 2), plain.lca(1, 2));
+}
+
+#[test]
+fn traversals_are_lazy_and_survive_deep_trees() {
+    let tree = parse_nwk("((A,B)x,C,(D)y)r;").unwrap();
+    let labels = |ids: Vec<usize>| ids.into_iter().map(|id| tree.node_label(id).unwrap()).collect::<Vec<_>>();
+    assert_eq!(labels(tree.preorder_iter().collect()), ["r", "x", "A", "B", "C", "y", "D"]);
+    assert_eq!(labels(tree.postorder_iter().collect()), ["A", "B", "x", "C", "D", "y", "r"]);
+    assert_eq!(labels(tree.levelorder_iter().collect()), ["r", "x", "C", "y", "A", "B", "D"]);
+    assert_eq!(tree.preorder_iter().nth(2), tree.preorder().get(2).copied());
+    assert_eq!(Tree::new().preorder_iter().count(), 0);
+    assert_eq!(tree.to_ascii().lines().next(), Some("r"));
+    assert_eq!(tree.to_ascii().lines().count(), 12); // 7 nodes, 3 polytomy connectors, one fork, one unary bar
+
+    // A caterpillar with 200k leaves is far deeper than the default thread stack allows for recursion.
+    let n = 200_000;
+    let mut newick = "(".repeat(n - 1);
+    newick.push_str("t0");
+    for i in 1..n {
+        newick.push_str(&format!(",t{})", i));
+    }
+    newick.push(';');
+    let deep = parse_nwk(&newick).unwrap();
+    assert_eq!(deep.postorder_iter().count(), 2 * n - 1);
+    assert_eq!(deep.preorder().len(), 2 * n - 1);
+    assert_eq!(deep.levelorder_iter().last().map(|id| deep.node_label(id)), Some(Some("t1"))); // The deepest cherry is (t0,t1)
+    assert_eq!(deep.compute_subtree_leaf_counts()[deep.root], n);
+    assert_eq!(deep.to_newick().len(), newick.len());
+}
+
+#[test]
+fn validate_reports_broken_links_and_duplicate_taxa() {
+    use filigineacht_rs::tree::validate::TreeIssue;
+
+    assert!(parse_nwk("((A:1,B:2),(C,D));").unwrap().is_valid());
+    assert_eq!(Tree::new().validate(), vec![TreeIssue::EmptyTree]);
+
+    let mut dup = parse_nwk("((A,B),(A,C));").unwrap();
+    dup.nodes[6].label = None; // C
+    let issues = dup.validate();
+    assert!(matches!(&issues[..], [TreeIssue::UnlabeledLeaf(6), TreeIssue::DuplicateLabel { label, nodes }] if label == "A" && nodes == &[2, 5]));
+
+    // Hand-edited links: A (node 2) claims the root as its parent, and B (node 3) is detached and its own parent.
+    let mut broken = parse_nwk("((A,B),C);").unwrap();
+    broken.nodes[2].parent = Some(0);
+    broken.nodes[1].children.retain(|&c| c != 3);
+    broken.nodes[3].parent = Some(3);
+    let issues = broken.validate();
+    assert!(issues.contains(&TreeIssue::ParentMismatch { node: 2, listed_by: 1 }));
+    assert!(issues.contains(&TreeIssue::NotAChildOfParent { node: 2, parent: 0 }));
+    assert!(issues.contains(&TreeIssue::Cycle(3)));
+    assert!(issues.contains(&TreeIssue::Unreachable(3)));
+    assert!(!broken.is_valid());
+
+    let mut repeated = parse_nwk("((A,B),C);").unwrap();
+    repeated.nodes[1].children.push(2);
+    assert_eq!(repeated.validate(), vec![TreeIssue::DuplicateChild { parent: 1, child: 2 }]);
+
+    // A star tree with a very high-degree root validates in linear time.
+    let star = format!("({});", (0..50_000).map(|i| format!("t{}", i)).collect::<Vec<_>>().join(","));
+    assert!(parse_nwk(&star).unwrap().is_valid());
+
+    // Strict parsing rejects the tree and points at the repeated label.
+    let strict = ParseOptions { strict: true, ..Default::default() };
+    let err = parse_nwk_with("((A,B),(C,A));", &strict).unwrap_err();
+    assert!(matches!(err.kind, ParseErrorKind::InvalidTree(_)));
+    assert_eq!(err.position.offset, 10);
+    assert_eq!(err.snippet, "A");
+    assert!(parse_nwk_with("((A,B),(C,D));", &strict).is_ok());
+    let read: Vec<_> = NewickReader::new("(A,B);\n(A,A);\n".as_bytes()).with_options(strict).collect();
+    assert!(read[0].is_ok() && read[1].as_ref().is_err_and(|e| e.tree_index == Some(1)));
+}
+
+#[test]
+fn spr_and_nni_moves_keep_invariants_and_undo_exactly() {
+    let mut taxa = TaxonSet::new();
+    let original = parse_nwk_in("((A:1,B:2)0.9:1,(C:1,(D:1,E:1):0.5):2,F:3);", &mut taxa).unwrap();
+    let total = |t: &Tree| t.preorder().iter().filter_map(|&id| t.nodes[id].length_to_parent).sum::<f64>();
+    let label = |t: &Tree, name: &str| t.leaves().into_iter().find(|&l| t.node_label(l) == Some(name)).unwrap();
+
+    let mut tree = original.clone();
+    let (b, d) = (label(&tree, "B"), label(&tree, "D"));
+    let edit = tree.spr(b, d, 0.5).unwrap();
+    assert_eq!(tree.to_newick(), "(A:2,(C:1,((D:0.5,B:2):0.5,E:1):0.5):2,F:3);");
+    assert!(tree.is_valid());
+    tree.undo(edit);
+    assert_eq!(tree.to_newick(), original.to_newick());
+    assert_eq!(tree.nodes.len(), original.nodes.len());
+
+    // Every SPR and NNI move keeps the tree valid, the leaf set and the total length, and undoes cleanly.
+    let n = original.nodes.len();
+    for subtree in 0..n {
+        for target in 0..n {
+            let Ok(edit) = tree.spr(subtree, target, 0.25) else { continue };
+            assert!(tree.is_valid(), "spr {} -> {}", subtree, target);
+            assert_eq!(tree.leaves().len(), 6);
+            assert!((total(&tree) - total(&original)).abs() < 1e-9);
+            tree.undo(edit);
+            assert_eq!(tree.to_newick(), original.to_newick());
+        }
+        for (child, sibling) in original.nni_moves(subtree) {
+            let edit = tree.nni(child, sibling).unwrap();
+            assert!(tree.is_valid());
+            assert_eq!(tree.robinson_foulds(&original, taxa.len()).unwrap().distance, 2); // One split replaced
+            tree.undo(edit);
+            assert_eq!(tree.to_newick(), original.to_newick());
+        }
+    }
+    assert_eq!(original.nni_moves(label(&original, "B")), vec![]);
+    assert_eq!(original.nni_moves(1).len(), 4); // (A,B) has two children and two siblings under the trifurcating root
+
+    // Detach and graft separately, including a graft above the root.
+    let a = label(&tree, "A");
+    let detach = tree.detach(a).unwrap();
+    assert_eq!(tree.to_newick(), "(B:3,(C:1,(D:1,E:1):0.5):2,F:3);");
+    let graft = tree.graft(a, tree.root, 0.5).unwrap();
+    assert_eq!(tree.to_newick(), "((B:3,(C:1,(D:1,E:1):0.5):2,F:3),A:1);");
+    tree.undo(graft);
+    tree.undo(detach);
+    assert_eq!(tree.to_newick(), original.to_newick());
+
+    assert_eq!(tree.detach(tree.root).err(), Some(TreeError::InvalidNode(tree.root)));
+    assert!(tree.spr(1, label(&tree, "A"), 0.5).is_err()); // Target inside the pruned subtree
+    assert!(tree.graft(a, 1, 0.5).is_err()); // A is still attached
+}
+
+#[test]
+fn canonical_form_and_topology_counts() {
+    use filigineacht_rs::tree::canonical::unique_topologies;
+
+    let mut taxa = TaxonSet::from_names(["A", "B", "C", "D", "E"]);
+    let trees: Vec<Tree> = [
+        "((A:1,B:2):1,(C,(D,E)));",
+        "(((E,D):0.1,C)x,(B,A)95);", // Same rooted topology, other child order and annotations
+        "(A,(B,(C,(D,E))));", // Same unrooted topology, different root
+        "((A,C),(B,(D,E)));",
+        "((B,A),((D,E),C));",
+    ]
+    .iter()
+    .map(|s| parse_nwk_in(s, &mut taxa).unwrap())
+    .collect();
+
+    assert_eq!(trees[0].topology_hash(), trees[1].topology_hash());
+    assert_ne!(trees[0].topology_hash(), trees[2].topology_hash());
+    assert_eq!(trees[0].unrooted_topology_hash(), trees[2].unrooted_topology_hash());
+    assert_ne!(trees[0].unrooted_topology_hash(), trees[3].unrooted_topology_hash());
+
+    let mut canonical = trees[1].clone();
+    canonical.canonicalize();
+    assert_eq!(canonical.to_newick_with(&NewickOptions::topology_only()), "((A,B),(C,(D,E)));");
+
+    let rooted = unique_topologies(&trees, true);
+    assert_eq!(rooted.iter().map(|c| c.trees.clone()).collect::<Vec<_>>(), vec![vec![0, 1, 4], vec![2], vec![3]]);
+    assert_eq!(rooted[0].hash, trees[0].topology_hash());
+    let unrooted = unique_topologies(&trees, false);
+    assert_eq!(unrooted.len(), 2);
+    assert_eq!((unrooted[0].count(), unrooted[0].representative()), (4, 0));
+
+    // Unbound trees are split by label, so different topologies on the same leaves stay apart.
+    let unbound: Vec<Tree> = ["((A,B),(C,D));", "((A,C),(B,D));", "(A,(B,(C,D)));"].iter().map(|s| parse_nwk(s).unwrap()).collect();
+    assert_ne!(unbound[0].unrooted_topology_hash(), unbound[1].unrooted_topology_hash());
+    assert_eq!(unbound[0].unrooted_topology_hash(), unbound[2].unrooted_topology_hash());
+    let classes = unique_topologies(&unbound, false);
+    assert_eq!(classes.iter().map(|c| c.trees.clone()).collect::<Vec<_>>(), vec![vec![0, 2], vec![1]]);
+}
+
+#[test]
+fn strict_majority_and_greedy_consensus() {
+    use filigineacht_rs::tree::consensus::{greedy_consensus, majority_consensus, strict_consensus};
+
+    let mut taxa = TaxonSet::from_names(["A", "B", "C", "D", "E"]);
+    let read = |newicks: &[&str], taxa: &mut TaxonSet| -> Vec<Tree> {
+        newicks.iter().map(|s| parse_nwk_in(s, taxa).unwrap()).collect()
+    };
+    let show = |mut tree: Tree| {
+        tree.canonicalize();
+        tree.to_newick()
+    };
+
+    let trees = read(&["((A,B),(C,(D,E)));", "((A,B),(D,(C,E)));", "((A,B),(C,(D,E)));", "((A,C),(B,(D,E)));", "((A,B),(E,(C,D)));"], &mut taxa);
+    assert_eq!(show(strict_consensus(&trees, &taxa).unwrap()), "(A,B,C,D,E);");
+    assert_eq!(show(majority_consensus(&trees, &taxa, 0.5).unwrap()), "(A,B,(C,(D,E)0.6)0.8);");
+    assert_eq!(show(majority_consensus(&trees, &taxa, 0.7).unwrap()), "(A,B,(C,D,E)0.8);");
+    assert_eq!(show(strict_consensus(&trees[..1], &taxa).unwrap()), "(A,B,(C,(D,E)1)1);");
+    assert!(majority_consensus(&trees, &taxa, 0.5).unwrap().is_valid());
+
+    // No split reaches a majority here, but greedy consensus still resolves compatible ones.
+    let ties = read(&["((A,B),(C,(D,E)));", "((A,B),(D,(C,E)));", "((A,C),(B,(D,E)));", "((A,D),(B,(C,E)));"], &mut taxa);
+    assert_eq!(show(majority_consensus(&ties, &taxa, 0.5).unwrap()), "(A,B,C,D,E);");
+    assert_eq!(show(greedy_consensus(&ties, &taxa).unwrap()), "(A,B,(D,(C,E)0.5)0.5);");
+
+    let other = read(&["((A,B),(C,D));"], &mut taxa);
+    assert_eq!(strict_consensus(&[trees[0].clone(), other[0].clone()], &taxa).err(), Some(TreeError::DifferentTaxa));
+    assert_eq!(majority_consensus(&trees, &taxa, 0.3).err(), Some(TreeError::InvalidFraction(0.3)));
+    assert_eq!(greedy_consensus(&[], &taxa).err(), Some(TreeError::NoTrees));
+}
+
+#[test]
+fn distance_matrices_average_and_export() {
+    use filigineacht_rs::tree::distance::{average_matrices, average_patristic_matrix};
+
+    let mut taxa = TaxonSet::from_names(["A", "B", "C", "D"]);
+    let tree = parse_nwk_in("((A:1,B:2):1,(C:1,D:3):0.5,E:1);", &mut taxa).unwrap();
+    let patristic = tree.patristic_matrix(&taxa);
+    let topological = tree.topological_matrix(&taxa);
+    for i in 0..taxa.len() {
+        for j in 0..taxa.len() {
+            let (x, y) = (tree.leaves_by_taxon(taxa.len())[i].unwrap(), tree.leaves_by_taxon(taxa.len())[j].unwrap());
+            assert_eq!(patristic.get(i, j), tree.patristic_distance(x, y));
+            assert_eq!(topological.get(i, j), tree.topological_distance(x, y).map(|d| d as f64));
+        }
+    }
+    assert_eq!(patristic.names(), taxa.names());
+    assert_eq!(
+        patristic.to_phylip().unwrap(),
+        "5\nA         0 3 3.5 5.5 3\nB         3 0 4.5 6.5 4\nC         3.5 4.5 0 4 2.5\nD         5.5 6.5 4 0 4.5\nE         3 4 2.5 4.5 0\n"
+    );
+
+    // A gene tree without D leaves D's pairs to the trees that have it.
+    let partial = parse_nwk_in("((A:1,B:1),(C:1,E:1));", &mut taxa).unwrap();
+    let partial_matrix = partial.patristic_matrix(&taxa);
+    assert_eq!(partial_matrix.get(0, 3), None);
+    assert!(!partial_matrix.is_complete() && partial_matrix.to_phylip().is_err());
+    let mean = average_patristic_matrix([&tree, &partial], &taxa).unwrap();
+    assert_eq!(mean.get(0, 1), Some(2.5));
+    assert_eq!(mean.get(0, 3), Some(5.5));
+    assert_eq!(average_matrices([&patristic, &partial_matrix]), Some(mean));
+}
+
+#[test]
+fn neighbor_joining_and_upgma_recover_their_trees() {
+    use filigineacht_rs::tree::construct::{neighbor_joining, upgma};
+    use filigineacht_rs::tree::distance::DistanceMatrix;
+
+    // NJ is exact on additive distances; compare the rebuilt tree's splits and distances with the source.
+    let mut taxa = TaxonSet::new();
+    let source = parse_nwk_in(
+        "((A:1,B:2):1,((C:1,D:3):0.5,(F:0.2,(G:1,H:0.4):0.7):1.1):0.3,(E:1,(I:2,J:0.1):0.6):0.2);",
+        &mut taxa,
+    ).unwrap();
+    let matrix = source.patristic_matrix(&taxa);
+    let mut nj = neighbor_joining(&matrix).unwrap();
+    nj.bind_taxa(&mut taxa);
+    assert!(nj.is_valid());
+    assert_eq!(nj.children(nj.root).len(), 3);
+    assert_eq!(nj.robinson_foulds(&source, taxa.len()).unwrap().distance, 0);
+    let rebuilt = nj.patristic_matrix(&taxa);
+    for i in 0..taxa.len() {
+        for j in 0..taxa.len() {
+            assert!((rebuilt.get(i, j).unwrap() - matrix.get(i, j).unwrap()).abs() < 1e-9);
+        }
+    }
+
+    // UPGMA is exact on ultrametric distances and returns the rooted tree.
+    let mut taxa = TaxonSet::new();
+    let clock = parse_nwk_in("(((A:1,B:1):1,C:2):1,(D:1.5,E:1.5):1.5);", &mut taxa).unwrap();
+    let mut rooted = upgma(&clock.patristic_matrix(&taxa)).unwrap();
+    rooted.bind_taxa(&mut taxa);
+    rooted.canonicalize();
+    assert_eq!(rooted.to_newick(), "((D:1.5,E:1.5):1.5,(C:2,(A:1,B:1):1):1);");
+
+    let mut small = DistanceMatrix::new(vec!["x".to_string(), "y".to_string()]);
+    assert_eq!(neighbor_joining(&small).err(), Some(TreeError::IncompleteMatrix));
+    small.set(0, 1, 3.0);
+    assert_eq!(neighbor_joining(&small).unwrap().to_newick(), "(x:1.5,y:1.5);");
+    assert_eq!(upgma(&small).unwrap().to_newick(), "(x:1.5,y:1.5);");
+    assert_eq!(upgma(&DistanceMatrix::new(Vec::new())).err(), Some(TreeError::EmptyTaxonSet));
+
+    // Infinite distances and overflowing row sums are rejected; large finite ones still give a valid tree
+    // even though every Q value overflows.
+    let names: Vec<String> = ["p", "q", "r", "s", "t"].iter().map(|s| s.to_string()).collect();
+    let filled = |value: &dyn Fn(usize, usize) -> f64| {
+        let mut matrix = DistanceMatrix::new(names.clone());
+        for i in 0..5 {
+            for j in i + 1..5 {
+                matrix.set(i, j, value(i, j));
+            }
+        }
+        matrix
+    };
+    let far = filled(&|_, j| if j == 4 { f64::INFINITY } else { 1.0 });
+    assert_eq!(neighbor_joining(&far).err(), Some(TreeError::InvalidDistance(f64::INFINITY)));
+    assert_eq!(upgma(&far).err(), Some(TreeError::InvalidDistance(f64::INFINITY)));
+    assert_eq!(neighbor_joining(&filled(&|_, _| 1e308)).err(), Some(TreeError::InvalidDistance(f64::INFINITY)));
+    let nj = neighbor_joining(&filled(&|_, _| 4e307)).unwrap();
+    assert_eq!(nj.leaves().len(), 5);
+    assert!(nj.is_valid());
+}
+
+#[test]
+fn seeded_generators_are_reproducible() {
+    use filigineacht_rs::tree::random::{TaxonNaming, TreeGenerator};
+
+    let yule = |seed| TreeGenerator::new(seed).yule(30, 1.0).unwrap().to_newick();
+    assert_eq!(yule(7), yule(7));
+    assert_ne!(yule(7), yule(8));
+
+    // Yule and reconstructed birth–death trees are ultrametric and binary.
+    let mut generator = TreeGenerator::new(42);
+    for tree in [generator.yule(40, 2.0).unwrap(), generator.birth_death(40, 1.0, 0.6).unwrap()] {
+        assert!(tree.is_valid());
+        assert_eq!(tree.leaves().len(), 40);
+        assert!(tree.nodes.iter().all(|n| n.children.is_empty() || n.children.len() == 2));
+        let heights: Vec<f64> = tree.leaves().into_iter().map(|l| tree.patristic_distance(tree.root, l).unwrap()).collect();
+        assert!(heights.iter().all(|h| (h - heights[0]).abs() < 1e-9 && *h > 0.0));
+    }
+
+    let mut letters = TreeGenerator::new(1).with_naming(TaxonNaming::Letters);
+    assert_eq!(letters.caterpillar(4).unwrap().to_newick(), "(((A,B),C),D);");
+    assert_eq!(letters.balanced(5).unwrap().to_newick(), "(((A,B),C),(D,E));");
+    assert_eq!(letters.caterpillar(1).unwrap().to_newick(), "A;");
+    let mut named = TreeGenerator::new(1).with_naming(TaxonNaming::Prefix("sp".to_string()));
+    assert_eq!(named.uniform(30).unwrap().taxa().len(), 30);
+    assert!(named.uniform(30).unwrap().taxa().contains(&"sp30"));
+    assert_eq!(TaxonNaming::Letters.names(28).unwrap()[26..], ["AA", "AB"]);
+
+    // Uniform topologies: all 15 rooted shapes-with-labels on four leaves turn up about equally often.
+    let mut taxa = TaxonSet::from_names(["t1", "t2", "t3", "t4"]);
+    let mut generator = TreeGenerator::new(3);
+    let trees: Vec<Tree> = (0..3000).map(|_| {
+        let mut tree = generator.uniform(4).unwrap();
+        tree.bind_taxa(&mut taxa);
+        tree
+    }).collect();
+    let classes = filigineacht_rs::tree::canonical::unique_topologies(&trees, true);
+    assert_eq!(classes.len(), 15);
+    assert!(classes.iter().all(|c| (150..=250).contains(&c.count())));
+
+    assert_eq!(generator.yule(0, 1.0).err(), Some(TreeError::EmptyTaxonSet));
+    assert_eq!(generator.birth_death(5, 1.0, 1.0).err(), Some(TreeError::InvalidRate(1.0)));
+    let mut short = TreeGenerator::new(1).with_naming(TaxonNaming::Custom(vec!["x".to_string()]));
+    assert_eq!(short.balanced(2).err(), Some(TreeError::NotEnoughNames(1)));
+}